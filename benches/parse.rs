@@ -0,0 +1,60 @@
+//! Time the compilation of a generated initial process of a million atoms.
+//!
+//! Run with `cargo bench --bench parse`, the number of atoms can be given as an argument.
+//! The time should grow about linearly with the number of atoms: in release builds a hundred
+//! thousand atoms parse in under two seconds, and a million in about twenty, close to half of
+//! it spent by pest. `tests/parse_large.rs` checks that the growth stays below quadratic.
+
+use std::{fmt::Write, time::Instant};
+
+use liblmntalc::{
+    analysis::{self, AnalysisOptions},
+    codegen::ILGenerator,
+    parser::{data::Symbol, parse_lmntal, ParseOptions},
+    source::SourceMap,
+};
+
+/// A ring of atoms connected by links, each holding a nested atom,
+/// with a membrane of two more atoms after every hundredth one, about `n` atoms in all.
+fn program(n: usize) -> String {
+    let mut source = String::new();
+    for i in 0..n / 2 {
+        let next = (i + 1) % (n / 2);
+        _ = write!(source, "node(L{}, L{}, val({}))", i, next, i);
+        if i % 100 == 0 {
+            _ = write!(source, ", m{{ a(M{}), b(M{}) }}", i, i);
+        }
+        // keep the lines short, like generated programs do
+        source.push_str(if i % 8 == 7 { ";\n" } else { ", " });
+    }
+    source.push_str("done");
+    source
+}
+
+fn main() {
+    let n = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(1_000_000);
+    let source = program(n);
+    println!("{} atoms, {} bytes", n, source.len());
+
+    let start = Instant::now();
+    let mut sources = SourceMap::new();
+    let file = sources.add("bench.lmn", source);
+    let Ok(Symbol::Membrane(root)) = parse_lmntal(&mut sources, file, &ParseOptions::default())
+    else {
+        panic!("the generated program does not parse");
+    };
+    println!("parse    {:>8.2?}", start.elapsed());
+
+    let start = Instant::now();
+    let diagnostics = analysis::analyze(root, &AnalysisOptions::default());
+    assert!(diagnostics.iter().all(|d| !d.is_error()));
+    println!("analyze  {:>8.2?}", start.elapsed());
+
+    let start = Instant::now();
+    let mut gen = ILGenerator::default();
+    gen.gen(Symbol::Membrane(root));
+    println!("generate {:>8.2?}", start.elapsed());
+}
@@ -0,0 +1,21 @@
+pub mod functor;
+pub mod link;
+pub mod types;
+
+use crate::{diagnostic::Diagnostic, parser::data::MembraneId};
+
+#[derive(Debug, Default)]
+pub struct AnalysisOptions {
+    /// Atom names that are intentionally used with several arities.
+    pub overloads: Vec<String>,
+}
+
+/// Run the analyses that only warn on the program rooted at the given membrane.
+///
+/// Links and guard types are checked by [`parse_lmntal`](crate::parser::parse_lmntal), since
+/// the generated code depends on them.
+pub fn analyze(root: MembraneId, options: &AnalysisOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = functor::check_functors(root, &options.overloads);
+    diagnostics.sort_by_key(|d| d.labels.first().map(|l| l.span));
+    diagnostics
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    functor::{FunctorId, FunctorTable},
+    parser::{
+        data::{Atom, MembraneId, Symbol},
+        ATOMS, FUNCTORS, MEMS, RULES,
+    },
+};
+
+/// Where a functor is used.
+#[derive(Debug, Clone)]
+pub enum Place {
+    Init,
+    Head(String),
+    Body(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct FunctorUse {
+    pub place: Place,
+    pub span: Span,
+    /// The module of the rule the use is written in, if it was loaded with `use`.
+    pub module: Option<String>,
+}
+
+/// Every functor of the program, mapped to the places it is used.
+pub type FunctorUses = BTreeMap<FunctorId, Vec<FunctorUse>>;
+
+/// Collect the uses of the functors of the program rooted at the given membrane.
+///
+/// Data atoms such as numbers are not included.
+pub fn functor_uses(root: MembraneId) -> FunctorUses {
+    let mut uses = FunctorUses::new();
+    collect_mem(root, &mut uses);
+    uses
+}
+
+fn collect_mem(mem: MembraneId, uses: &mut FunctorUses) {
+    let (mems, atoms, rules) = unsafe {
+        (
+            MEMS.get().unwrap(),
+            ATOMS.get_or_init(BTreeMap::new),
+            RULES.get_or_init(BTreeMap::new),
+        )
+    };
+    let mem = &mems[&mem];
+    for symbol in &mem.process {
+        match symbol {
+            Symbol::Atom(id) => add(uses, &atoms[id], Place::Init, None),
+            Symbol::Membrane(id) => collect_mem(*id, uses),
+            _ => {}
+        }
+    }
+    for id in &mem.rule_set {
+        let rule = &rules[id];
+        let module = &rule.module;
+        for atom in &rule.atoms {
+            add(uses, atom, Place::Head(rule.name.clone()), module.clone());
+        }
+        for atoms in &rule.case_atoms {
+            for atom in atoms {
+                add(uses, atom, Place::Body(rule.name.clone()), module.clone());
+            }
+        }
+    }
+}
+
+fn add(uses: &mut FunctorUses, atom: &Atom, place: Place, module: Option<String>) {
+    if functors().get(atom.functor).is_data() {
+        return;
+    }
+    uses.entry(atom.functor).or_default().push(FunctorUse {
+        place,
+        span: atom.span,
+        module,
+    });
+}
+
+fn functors() -> &'static FunctorTable {
+    unsafe { FUNCTORS.get_or_init(FunctorTable::default) }
+}
+
+/// Warn about atom names used with several arities, except for the names listed in `overloads`,
+/// and about atoms matched by rule heads that are never created.
+pub fn check_functors(root: MembraneId, overloads: &[String]) -> Vec<Diagnostic> {
+    let uses = functor_uses(root);
+    let functors = functors();
+    let mut diagnostics = vec![];
+
+    let mut arities: BTreeMap<&str, Vec<(usize, FunctorId)>> = BTreeMap::new();
+    for id in uses.keys() {
+        let functor = functors.get(*id);
+        arities
+            .entry(&functor.name)
+            .or_default()
+            .push((functor.arity, *id));
+    }
+    for (name, mut arities) in arities {
+        if arities.len() < 2 || overloads.iter().any(|o| o == name) {
+            continue;
+        }
+        let mut d = Diagnostic::warning(format!(
+            "atom `{}` is used with {} different arities",
+            name,
+            arities.len()
+        ));
+        arities.sort();
+        for (arity, id) in arities {
+            let first = &uses[&id][0];
+            d = d.with_label(first.span, format!("`{}/{}` used here", name, arity));
+        }
+        diagnostics.push(d.with_note(format!(
+            "pass `--allow-overload {}` if this is intended",
+            name
+        )));
+    }
+
+    for (id, uses) in &uses {
+        let functor = functors.get(*id);
+        let produced = uses
+            .iter()
+            .any(|u| matches!(u.place, Place::Init | Place::Body(_)));
+        if produced {
+            continue;
+        }
+        // a module provides rules for atoms a program may never create
+        for u in uses.iter().filter(|u| u.module.is_none()) {
+            if let Place::Head(rule) = &u.place {
+                diagnostics.push(
+                    Diagnostic::warning(format!(
+                        "`{}` is matched by rule `{}` but never created",
+                        functor, rule
+                    ))
+                    .with_label(u.span, "no rule body or initial process creates this atom"),
+                );
+            }
+        }
+    }
+    diagnostics
+}
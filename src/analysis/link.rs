@@ -0,0 +1,244 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    parser::{
+        data::{Link, LinkId, MembraneId, Symbol},
+        rule_parser::{Rule, RULE_MEMBRANE},
+        ATOMS, LINKS, MEMS, RULES,
+    },
+};
+
+use super::types::collect_rules;
+
+/// Check the links of the initial process and of every membrane in it.
+///
+/// Reports links that are used only once, link names that are used more than twice
+/// and links that connect atoms in different membranes without going through a proxy.
+pub fn check_links(root: MembraneId) -> Vec<Diagnostic> {
+    let mut ids = BTreeSet::new();
+    collect_links(root, &mut ids);
+
+    let (links, atoms) = unsafe {
+        (
+            LINKS.get_or_init(BTreeMap::new),
+            ATOMS.get_or_init(BTreeMap::new),
+        )
+    };
+    let mut by_name: HashMap<&str, Vec<LinkId>> = HashMap::new();
+    for id in &ids {
+        let link = &links[id];
+        if !link.name.is_empty() {
+            by_name.entry(&link.name).or_default().push(*id);
+        }
+    }
+
+    let mut diagnostics = vec![];
+    for id in &ids {
+        let link = &links[id];
+        if link.name.is_empty() {
+            continue;
+        }
+        let same_name = &by_name[link.name.as_str()];
+        if same_name.len() > 1 {
+            // report over-used names once, at their first occurrence
+            if same_name[0] == *id {
+                diagnostics.push(over_used(&link.name, same_name.iter().map(|id| &links[id])));
+            }
+            continue;
+        }
+        if link.link2.is_none() {
+            diagnostics.push(
+                Diagnostic::error(format!("link `{}` is not connected", link.name))
+                    .with_label(
+                        link.span1.unwrap_or_default(),
+                        format!("`{}` is used only here", link.name),
+                    )
+                    .with_note("free links are not allowed in the initial process"),
+            );
+        } else if let Some(d) = cross_membrane(link, |id| atoms.get(&id).map(|a| a.membrane)) {
+            diagnostics.push(d);
+        }
+    }
+    diagnostics
+}
+
+/// Check the links of the rules of the program rooted at `root`.
+///
+/// A name may be written at most twice in the head and the body of a case together. A link
+/// written once in the head must have its other end in the body of every case, or connect to a
+/// data atom whose type the guard checks, which the rule then removes. A link of a body may not
+/// cross a membrane, as in the initial process. Run after the types of the guards are inferred.
+pub fn check_rule_links(root: MembraneId) -> Vec<Diagnostic> {
+    let mut rule_ids = vec![];
+    collect_rules(root, &mut rule_ids);
+
+    let rules = unsafe { RULES.get_or_init(BTreeMap::new) };
+    let mut diagnostics = vec![];
+    for rule in rule_ids.iter().map(|id| &rules[id]) {
+        let mut reported: Vec<&str> = vec![];
+        for case in &rule.cases {
+            let mut by_name: BTreeMap<&str, Vec<&Link>> = BTreeMap::new();
+            for link in rule.links.values().chain(rule.case_links[case.id].values()) {
+                by_name.entry(&link.name).or_default().push(link);
+            }
+            for (name, links) in by_name {
+                let uses: usize = links.iter().map(|link| link.link2.map_or(1, |_| 2)).sum();
+                // a temporary variable may be written any number of times in the body
+                let temp_var = case.vars.iter().any(|var| var.name == name);
+                if uses > 2 && !temp_var && !reported.contains(&name) {
+                    diagnostics.push(over_used(name, links));
+                    reported.push(name);
+                }
+            }
+        }
+
+        for case in &rule.cases {
+            for link in rule.case_links[case.id].values() {
+                if reported.contains(&link.name.as_str()) {
+                    continue;
+                }
+                if let Some(d) = body_cross_membrane(rule, case.id, link) {
+                    diagnostics.push(d);
+                    reported.push(&link.name);
+                }
+            }
+        }
+
+        for (id, link) in &rule.links {
+            if link.link2.is_some() || reported.contains(&link.name.as_str()) {
+                continue;
+            }
+            let dangling = rule.cases.iter().any(|case| {
+                let in_body = rule.case_links[case.id]
+                    .values()
+                    .any(|body| body.name == link.name && body.link2.is_none());
+                let typed = case
+                    .guard_types
+                    .iter()
+                    .all(|clause| clause.symbols.contains_key(&Symbol::Link(*id)));
+                !in_body && !typed
+            });
+            if dangling {
+                diagnostics.push(
+                    Diagnostic::error(format!(
+                        "link `{}` of the head of rule `{}` is used only once",
+                        link.name, rule.name
+                    ))
+                    .with_label(
+                        link.span1.unwrap_or_default(),
+                        format!("`{}` is used only here", link.name),
+                    )
+                    .with_note(
+                        "a link of the head goes on in the body, or connects to a data atom \
+                         the guard checks the type of",
+                    ),
+                );
+            }
+        }
+    }
+    diagnostics
+}
+
+fn collect_links(mem: MembraneId, ids: &mut BTreeSet<LinkId>) {
+    let (mems, atoms) = unsafe { (MEMS.get().unwrap(), ATOMS.get_or_init(BTreeMap::new)) };
+    for symbol in &mems[&mem].process {
+        match symbol {
+            Symbol::Atom(id) => {
+                for link in &atoms[id].links {
+                    if let Symbol::Link(link) = link {
+                        ids.insert(*link);
+                    }
+                }
+            }
+            Symbol::Membrane(id) => collect_links(*id, ids),
+            _ => {}
+        }
+    }
+}
+
+fn over_used<'l>(name: &str, links: impl IntoIterator<Item = &'l Link> + Clone) -> Diagnostic {
+    let mut d = Diagnostic::error(format!(
+        "link `{}` is used {} times, but a link connects exactly two ports",
+        name,
+        links
+            .clone()
+            .into_iter()
+            .map(|link| link.link2.map_or(1, |_| 2))
+            .sum::<usize>()
+    ));
+    for link in links {
+        for span in [link.span1, link.span2].into_iter().flatten() {
+            d = d.with_label(span, format!("`{}` used here", name));
+        }
+    }
+    d
+}
+
+/// The error for a link of the body of case `case` that crosses a membrane, as in the initial
+/// process. The membranes of a body are new, so a link going on from the head crosses one when
+/// exactly one of its ends is written directly in the rule.
+fn body_cross_membrane(rule: &Rule, case: usize, link: &Link) -> Option<Diagnostic> {
+    let body = |id| {
+        rule.case_atoms[case]
+            .iter()
+            .find(|atom| atom.id == id)
+            .map(|atom| atom.membrane)
+    };
+    if link.link2.is_some() {
+        return cross_membrane(link, body);
+    }
+    let head = rule
+        .links
+        .values()
+        .find(|head| head.name == link.name && head.link2.is_none())?;
+    let (Some((Symbol::Atom(head_atom), _)), Some((Symbol::Atom(body_atom), _))) =
+        (head.link1, link.link1)
+    else {
+        return None;
+    };
+    let head_mem = rule
+        .atoms
+        .iter()
+        .find(|atom| atom.id == head_atom)?
+        .membrane;
+    if (head_mem == RULE_MEMBRANE) == (body(body_atom)? == RULE_MEMBRANE) {
+        return None;
+    }
+    Some(crossing(
+        &link.name,
+        head.span1.unwrap_or_default(),
+        link.span1.unwrap_or_default(),
+    ))
+}
+
+/// The error for a link connecting two atoms of different membranes, if `link` does, where
+/// `membrane_of` gives the membrane of an atom by its id.
+fn cross_membrane(
+    link: &Link,
+    membrane_of: impl Fn(usize) -> Option<MembraneId>,
+) -> Option<Diagnostic> {
+    let end = |end: Option<(Symbol, usize)>| match end {
+        Some((Symbol::Atom(id), _)) => membrane_of(id),
+        _ => None,
+    };
+    let (mem1, mem2) = (end(link.link1)?, end(link.link2)?);
+    if mem1 == mem2 {
+        return None;
+    }
+    Some(crossing(
+        &link.name,
+        link.span1.unwrap_or_default(),
+        link.span2.unwrap_or_default(),
+    ))
+}
+
+fn crossing(name: &str, one: Span, other: Span) -> Diagnostic {
+    Diagnostic::error(format!(
+        "link `{}` connects atoms in different membranes without a proxy",
+        name
+    ))
+    .with_label(one, "one end is here")
+    .with_label(other, "the other end is here")
+    .with_note("links crossing a membrane boundary are not supported yet")
+}
@@ -0,0 +1,398 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    parser::{
+        data::{MembraneId, Symbol},
+        rule_parser::{
+            Case, GuardFunction, GuardNode, GuardOperator, GuardTypes, Rule, TempVar, Type,
+        },
+        MEMS, RULES,
+    },
+};
+
+/// Infer the types of the guard variables of every rule in the program rooted at `root`,
+/// and record them on the cases of the rules.
+pub fn check_types(root: MembraneId) -> Vec<Diagnostic> {
+    let mut rule_ids = vec![];
+    collect_rules(root, &mut rule_ids);
+
+    let mut diagnostics = vec![];
+    for id in rule_ids {
+        let rule = unsafe { RULES.get_mut().unwrap().get_mut(&id).unwrap() };
+        let mut cases = std::mem::take(&mut rule.cases);
+        for case in cases.iter_mut() {
+            let (types, mut errors) = infer_case(rule, case);
+            case.guard_types = types;
+            diagnostics.append(&mut errors);
+        }
+        rule.cases = cases;
+    }
+    diagnostics
+}
+
+pub(crate) fn collect_rules(mem: MembraneId, rules: &mut Vec<usize>) {
+    let mem = unsafe { &MEMS.get().unwrap()[&mem] };
+    rules.extend(mem.rule_set.iter().copied());
+    for symbol in &mem.process {
+        if let Symbol::Membrane(id) = symbol {
+            collect_rules(*id, rules);
+        }
+    }
+}
+
+/// Infer the types of each clause of the guard of `case`.
+pub fn infer_case(rule: &Rule, case: &Case) -> (Vec<GuardTypes>, Vec<Diagnostic>) {
+    let clauses = match &case.constraint {
+        Some(guard) => guard.clauses(),
+        None => vec![vec![]],
+    };
+    let mut types = vec![];
+    let mut diagnostics: Vec<Diagnostic> = vec![];
+    for clause in clauses {
+        let mut infer = Infer::new(rule, &case.vars);
+        // type checks first, so the result does not depend on the order of the conditions
+        let (checks, rest): (Vec<_>, Vec<_>) = clause
+            .iter()
+            .partition(|cond| matches!(cond, GuardNode::TypeConstraint(..)));
+        for cond in checks.into_iter().chain(rest) {
+            infer.condition(cond);
+        }
+        // the variables no condition uses
+        for i in 0..case.vars.len() {
+            infer.expr(&GuardNode::Var(i));
+        }
+        infer.check_resolved();
+        types.push(infer.types());
+        // the same mistake shows up in every clause that contains it
+        for d in infer.diagnostics {
+            if !diagnostics.iter().any(|e| {
+                e.message == d.message
+                    && e.labels.first().map(|l| l.span) == d.labels.first().map(|l| l.span)
+            }) {
+                diagnostics.push(d);
+            }
+        }
+    }
+    (types, diagnostics)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ty {
+    Int,
+    Float,
+    String,
+    Bool,
+    Ground,
+}
+
+impl Ty {
+    fn name(&self) -> &'static str {
+        match self {
+            Ty::Int => "int",
+            Ty::Float => "float",
+            Ty::String => "string",
+            Ty::Bool => "a condition",
+            Ty::Ground => "ground",
+        }
+    }
+}
+
+/// Unification over the type variables of one guard clause.
+struct Infer<'a> {
+    rule: &'a Rule,
+    parent: Vec<usize>,
+    types: Vec<Option<Ty>>,
+    /// Where a type variable is first required to be a number.
+    numeric: Vec<Option<Span>>,
+    symbols: HashMap<Symbol, usize>,
+    temp_vars: &'a [TempVar],
+    /// Type variable of each temporary variable, once its expression has been visited.
+    vars: Vec<Option<usize>>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Infer<'a> {
+    fn new(rule: &'a Rule, temp_vars: &'a [TempVar]) -> Self {
+        Self {
+            rule,
+            parent: vec![],
+            types: vec![],
+            numeric: vec![],
+            symbols: HashMap::new(),
+            temp_vars,
+            vars: vec![None; temp_vars.len()],
+            diagnostics: vec![],
+        }
+    }
+
+    fn fresh(&mut self, ty: Option<Ty>) -> usize {
+        self.parent.push(self.parent.len());
+        self.types.push(ty);
+        self.numeric.push(None);
+        self.parent.len() - 1
+    }
+
+    fn find(&mut self, v: usize) -> usize {
+        let p = self.parent[v];
+        if p == v {
+            return v;
+        }
+        let root = self.find(p);
+        self.parent[v] = root;
+        root
+    }
+
+    fn ty(&mut self, v: usize) -> Option<Ty> {
+        let v = self.find(v);
+        self.types[v]
+    }
+
+    /// Merge two type variables, returning both types if they are known and differ.
+    fn unify(&mut self, a: usize, b: usize) -> Result<(), (Ty, Ty)> {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return Ok(());
+        }
+        match (self.types[a], self.types[b]) {
+            (Some(ta), Some(tb)) if ta != tb => return Err((ta, tb)),
+            (None, tb) => self.types[a] = tb,
+            _ => {}
+        }
+        self.numeric[a] = self.numeric[a].or(self.numeric[b]);
+        self.parent[b] = a;
+        Ok(())
+    }
+
+    fn symbol(&mut self, symbol: Symbol) -> usize {
+        if let Some(v) = self.symbols.get(&symbol) {
+            return *v;
+        }
+        let v = self.fresh(None);
+        self.symbols.insert(symbol, v);
+        v
+    }
+
+    fn symbol_name(&self, symbol: Symbol) -> String {
+        match symbol {
+            Symbol::Link(id) => self.rule.links[&id].name.clone(),
+            Symbol::ProcContext(id) => format!("${}", self.rule.procs[id].name),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Require `v` to be of type `ty`, `what` names the expected values for the message.
+    fn expect(&mut self, v: usize, ty: Ty, span: Span, op: &dyn Display, what: &str) {
+        let t = self.fresh(Some(ty));
+        if let Err((found, _)) = self.unify(v, t) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("`{}` expects {}, found {}", op, what, found.name()))
+                    .with_label(span, "in this expression"),
+            );
+        }
+    }
+
+    /// Report a mismatch between the types of two operands, if any.
+    fn unify_operands(&mut self, a: usize, b: usize, span: Span, op: &dyn Display) -> bool {
+        match self.unify(a, b) {
+            Ok(()) => true,
+            Err((ta, tb)) => {
+                let mut d = Diagnostic::error(format!(
+                    "mismatched types: `{}` between {} and {}",
+                    op,
+                    ta.name(),
+                    tb.name()
+                ))
+                .with_label(span, "operands must have the same type");
+                let numeric = |t| matches!(t, Ty::Int | Ty::Float);
+                if numeric(ta) && numeric(tb) {
+                    d = d.with_help("convert one side with `int(...)` or `float(...)`");
+                }
+                self.diagnostics.push(d);
+                false
+            }
+        }
+    }
+
+    fn require_numeric(&mut self, v: usize, span: Span, op: &dyn Display) {
+        let root = self.find(v);
+        match self.types[root] {
+            Some(ty @ (Ty::String | Ty::Bool | Ty::Ground)) => self.diagnostics.push(
+                Diagnostic::error(format!("`{}` expects numbers, found {}", op, ty.name()))
+                    .with_label(span, "in this expression"),
+            ),
+            _ => {
+                self.numeric[root].get_or_insert(span);
+            }
+        }
+    }
+
+    fn condition(&mut self, node: &GuardNode) {
+        let v = self.expr(node);
+        match self.ty(v) {
+            Some(Ty::Bool) => {}
+            ty => {
+                let mut d = Diagnostic::error(format!(
+                    "a guard condition must be a comparison or a type check, found {}",
+                    ty.map_or("a value", |t| t.name())
+                ));
+                if let GuardNode::Operation(_, _, _, span) = node {
+                    d = d.with_label(*span, "used as a condition here");
+                }
+                self.diagnostics.push(d);
+            }
+        }
+    }
+
+    fn expr(&mut self, node: &GuardNode) -> usize {
+        match node {
+            GuardNode::IntValue(_) => self.fresh(Some(Ty::Int)),
+            GuardNode::FloatValue(_) => self.fresh(Some(Ty::Float)),
+            GuardNode::Value(symbol) => self.symbol(*symbol),
+            // a variable is typed where it is first used, as a condition may come before
+            // a `with` clause it depends on; its expression only uses earlier variables
+            GuardNode::Var(i) => match self.vars[*i] {
+                Some(ty) => ty,
+                None => {
+                    let ty = self.expr(&self.temp_vars[*i].value);
+                    self.vars[*i] = Some(ty);
+                    ty
+                }
+            },
+            GuardNode::TypeConstraint(ty, args, span) => {
+                let ty = match ty {
+                    Type::Int => Some(Ty::Int),
+                    Type::Float => Some(Ty::Float),
+                    Type::String => Some(Ty::String),
+                    Type::Ground => Some(Ty::Ground),
+                    _ => None,
+                };
+                if let Some(ty) = ty {
+                    for arg in args {
+                        let v = self.symbol(*arg);
+                        let t = self.fresh(Some(ty));
+                        if let Err((found, _)) = self.unify(v, t) {
+                            let name = self.symbol_name(*arg);
+                            self.diagnostics.push(
+                                Diagnostic::error(format!(
+                                    "`{}` is required to be {} here, but it is {}",
+                                    name,
+                                    ty.name(),
+                                    found.name()
+                                ))
+                                .with_label(*span, "conflicting type check"),
+                            );
+                        }
+                    }
+                }
+                self.fresh(Some(Ty::Bool))
+            }
+            GuardNode::Operation(op, lhs, rhs, span) => {
+                let (a, b) = (self.expr(lhs), self.expr(rhs));
+                if let GuardOperator::And | GuardOperator::Or = op {
+                    self.expect(a, Ty::Bool, *span, op, "conditions");
+                    self.expect(b, Ty::Bool, *span, op, "conditions");
+                    return self.fresh(Some(Ty::Bool));
+                }
+                // ground structures can be compared for equality, but nothing else
+                let structural = matches!(op, GuardOperator::Eq | GuardOperator::Neq)
+                    && self.ty(a).or(self.ty(b)) == Some(Ty::Ground);
+                if self.unify_operands(a, b, *span, op) && !structural {
+                    self.require_numeric(a, *span, op);
+                    if op.is_float() {
+                        self.expect(a, Ty::Float, *span, op, "floats");
+                    } else if *op == GuardOperator::Mod {
+                        self.expect(a, Ty::Int, *span, op, "ints");
+                    }
+                }
+                if op.is_comparison() {
+                    self.fresh(Some(Ty::Bool))
+                } else {
+                    a
+                }
+            }
+            GuardNode::MemPredicate(..) => self.fresh(Some(Ty::Bool)),
+            // negating a type check does not tell anything about the type
+            GuardNode::Not(inner, _) => {
+                match inner.as_ref() {
+                    GuardNode::TypeConstraint(_, args, _) => {
+                        for arg in args {
+                            self.symbol(*arg);
+                        }
+                    }
+                    _ => self.condition(inner),
+                }
+                self.fresh(Some(Ty::Bool))
+            }
+            GuardNode::Call(func, args, span) => {
+                let args: Vec<usize> = args.iter().map(|arg| self.expr(arg)).collect();
+                let x = args[0];
+                if let [a, b] = args[..] {
+                    self.unify_operands(a, b, *span, func);
+                }
+                self.require_numeric(x, *span, func);
+                match func {
+                    GuardFunction::Int => self.fresh(Some(Ty::Int)),
+                    GuardFunction::Float => self.fresh(Some(Ty::Float)),
+                    GuardFunction::Sqrt => {
+                        self.expect(x, Ty::Float, *span, func, "floats");
+                        x
+                    }
+                    GuardFunction::Abs | GuardFunction::Min | GuardFunction::Max => x,
+                }
+            }
+        }
+    }
+
+    /// Report numbers whose type is not determined by the clause.
+    fn check_resolved(&mut self) {
+        let mut symbols: Vec<(Symbol, usize)> =
+            self.symbols.iter().map(|(s, v)| (*s, *v)).collect();
+        symbols.sort_by(|a, b| a.0.compare(&b.0));
+        let mut reported = vec![];
+        for (symbol, v) in symbols {
+            let root = self.find(v);
+            if let (None, Some(span)) = (self.types[root], self.numeric[root]) {
+                if reported.contains(&root) {
+                    continue;
+                }
+                reported.push(root);
+                let name = self.symbol_name(symbol);
+                self.diagnostics.push(
+                    Diagnostic::error(format!(
+                        "cannot infer whether `{}` is an int or a float",
+                        name
+                    ))
+                    .with_label(span, format!("`{}` is used as a number here", name))
+                    .with_help(format!(
+                        "add `int({})` or `float({})` to the guard",
+                        name, name
+                    )),
+                );
+            }
+        }
+    }
+
+    fn types(&mut self) -> GuardTypes {
+        let to_type = |ty: Option<Ty>| match ty {
+            Some(Ty::Int) => Some(Type::Int),
+            Some(Ty::Float) => Some(Type::Float),
+            Some(Ty::String) => Some(Type::String),
+            Some(Ty::Ground) => Some(Type::Ground),
+            _ => None,
+        };
+        let mut types = GuardTypes::default();
+        let symbols: Vec<(Symbol, usize)> = self.symbols.iter().map(|(s, v)| (*s, *v)).collect();
+        for (symbol, v) in symbols {
+            if let Some(ty) = to_type(self.ty(v)) {
+                types.symbols.insert(symbol, ty);
+            }
+        }
+        let vars = self.vars.clone();
+        types.vars = vars
+            .into_iter()
+            .map(|v| to_type(self.ty(v.expect("every variable is visited"))))
+            .collect();
+        types
+    }
+}
@@ -1,136 +1,194 @@
-use std::{collections::HashMap, fmt::Display};
+use std::{collections::BTreeMap, fmt::Display};
 
 use colored::Colorize;
 
-use crate::parser::{
-    data::{Membrane, Symbol},
-    rule_parser::Rule,
-    MEMS, RULES,
+use crate::{
+    functor::FunctorTable,
+    parser::{
+        data::Symbol,
+        rule_parser::{Case, Rule},
+        ATOMS, FUNCTORS, LINKS, MEMS, RULES,
+    },
+    source::SourceMap,
 };
 
-use self::{il::IL, rule_gen::RuleIL};
+use self::il::{Instr, RuleSetId};
 
+pub mod binary;
+mod guard_gen;
 pub mod il;
+pub mod liveness;
+mod regalloc;
 mod rule_gen;
+mod verify;
 
+pub use self::rule_gen::{HistoryTable, RuleIL};
+
+/// Output format of the compiler.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Target {
+    #[default]
+    #[value(alias = "il")]
+    #[serde(alias = "il")]
     Text,
     Binary,
 }
 
+impl Target {
+    /// Extension of the files written in this format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Target::Text => "il",
+            Target::Binary => "lmnil",
+        }
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct ILGenerator {
-    init_rule: Vec<IL>,
-    /// Membrane ID -> Rule Set
-    /// Rule set contains rules in the membrane.
-    rule_sets: HashMap<usize, Vec<RuleIL>>,
+    /// Functors of the atoms the instructions create and find, taken over from the parser.
+    functors: FunctorTable,
+    /// The initial process, a rule with an empty head.
+    init_rule: RuleIL,
+    /// The rules of each rule set. The rule sets are numbered in the order the instructions
+    /// loading them are generated, since the membranes of rule bodies have no global id.
+    rule_sets: BTreeMap<RuleSetId, Vec<RuleIL>>,
+    /// Number of rule sets given out so far.
+    rule_set_count: usize,
 }
 
 impl Display for ILGenerator {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{}", "Init".magenta())?;
-        for il in &self.init_rule {
-            writeln!(f, "{}", il)?;
-        }
-        writeln!(f)?;
-        for (mem_id, rule_set) in &self.rule_sets {
-            writeln!(f, "{} {}", "RuleSet".blue(), mem_id)?;
-            for rule in rule_set {
-                writeln!(f, "{}", rule)?;
+        self.write(f, None)
+    }
+}
+
+/// The text IL with the source line of the instructions written before them as comments.
+pub struct Annotated<'a> {
+    gen: &'a ILGenerator,
+    sources: &'a SourceMap,
+}
+
+impl Display for Annotated<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.gen.write(f, Some(self.sources))
+    }
+}
+
+/// Write the instructions of a block, each line of the source they come from
+/// written once before the first of them when `sources` is given.
+pub(crate) fn write_block(
+    f: &mut std::fmt::Formatter<'_>,
+    block: &[Instr],
+    functors: &FunctorTable,
+    sources: Option<&SourceMap>,
+) -> std::fmt::Result {
+    let mut last = None;
+    for instr in block {
+        let location = sources
+            .zip(instr.span)
+            .and_then(|(sources, span)| Some((sources, sources.location(span.start)?)));
+        if let Some((sources, location)) = location {
+            if last != Some((location.file, location.line)) {
+                let file = sources.get(location.file);
+                let text = file.text.lines().nth(location.line - 1).unwrap_or("");
+                let comment = format!(
+                    "// {}:{}: {}",
+                    file.path.display(),
+                    location.line,
+                    text.trim()
+                );
+                writeln!(f, "{}", comment.bright_black())?;
+                last = Some((location.file, location.line));
             }
         }
-        Ok(())
+        writeln!(f, "{}", instr.il.display(functors))?;
     }
+    Ok(())
 }
 
 impl ILGenerator {
-    /// Entry point of code generation.
-    pub fn gen(&mut self, symbol: Symbol) {
-        match symbol {
-            Symbol::Membrane(id) => {
-                let mem = unsafe { MEMS.get().unwrap().get(&id).unwrap() };
-                self.gen_init_mem(mem);
-            }
-            _ => unreachable!(),
-        }
+    /// The text IL annotated with the lines of `sources` the instructions are generated from.
+    pub fn annotated<'a>(&'a self, sources: &'a SourceMap) -> Annotated<'a> {
+        Annotated { gen: self, sources }
     }
 
-    fn gen_init_mem(&mut self, mem: &Membrane) {
-        for process in &mem.process {
-            let mut unit = self.gen_unit(*process);
-            self.init_rule.append(&mut unit);
+    fn write(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        sources: Option<&SourceMap>,
+    ) -> std::fmt::Result {
+        writeln!(f, "{}", "Init".magenta())?;
+        self.init_rule.write_blocks(f, &self.functors, sources)?;
+        writeln!(f)?;
+        for (id, rule_set) in &self.rule_sets {
+            writeln!(f, "{} {}", "RuleSet".blue(), id)?;
+            for rule in rule_set {
+                rule.write(f, &self.functors, sources)?;
+                writeln!(f)?;
+            }
         }
+        Ok(())
+    }
 
-        // while let Some(process) = self.queue.pop() {
-        //     self.gen_unit(process);
-        // }
-
-        for rule_id in &mem.rule_set {
-            let rule = unsafe { RULES.get().unwrap().get(rule_id).unwrap() };
-            let rule_il = gen_rule(rule);
-            self.emit_rule(mem.id, rule_il);
-        }
+    pub fn functors(&self) -> &FunctorTable {
+        &self.functors
     }
 
-    fn gen_unit(&mut self, symbol: Symbol) -> Vec<IL> {
-        match symbol {
-            Symbol::Atom(id) => {
-                let atom = unsafe { crate::parser::ATOMS.get().unwrap().get(&id).unwrap() };
-                ILGenerator::gen_atom(atom)
-            }
-            Symbol::Membrane(id) => {
-                let mem = unsafe { crate::parser::MEMS.get().unwrap().get(&id).unwrap() };
-                self.gen_mem(mem)
-            }
-            _ => {
-                unreachable!()
-            }
-        }
+    /// The initial process and the rules of every rule set.
+    pub(crate) fn rules(&self) -> impl Iterator<Item = &RuleIL> {
+        std::iter::once(&self.init_rule).chain(self.rule_sets.values().flatten())
     }
 
-    fn gen_atom(atom: &crate::parser::data::Atom) -> Vec<IL> {
-        let mut il = vec![IL::NewAtom(
-            atom.id,
-            atom.membrane,
-            atom.name.clone(),
-            atom.links.len(),
-        )];
-
-        for link in &atom.links {
-            if let Symbol::Link(id) = link {
-                let link = unsafe { crate::parser::LINKS.get().unwrap().get(id).unwrap() };
-                if let Some(link2) = link.link2 {
-                    if Into::<usize>::into(link2.0) == atom.id {
-                        il.push(IL::new_link(link, 0));
-                    }
-                }
-            }
-        }
+    /// The rules of every rule set, with the functors they refer to, for the optimizers to rewrite.
+    pub(crate) fn rules_mut(&mut self) -> (&FunctorTable, impl Iterator<Item = &mut RuleIL>) {
+        (&self.functors, self.rule_sets.values_mut().flatten())
+    }
 
-        il
+    /// Entry point of code generation.
+    ///
+    /// The initial process is generated as a rule with an empty head,
+    /// whose body is the root membrane.
+    pub fn gen(&mut self, symbol: Symbol) {
+        self.functors = unsafe { FUNCTORS.take() }.unwrap_or_default();
+        let Symbol::Membrane(id) = symbol else {
+            unreachable!()
+        };
+        let mut mems = unsafe { MEMS.take() }.unwrap_or_default();
+        let root = mems.remove(&id).unwrap();
+        let mut init = Rule::default();
+        init.span = root.span;
+        init.membrane = root.id;
+        init.cases.push(Case {
+            span: root.span,
+            body: root,
+            ..Default::default()
+        });
+        init.case_atoms.push(
+            unsafe { ATOMS.take() }
+                .unwrap_or_default()
+                .into_values()
+                .collect(),
+        );
+        init.case_links
+            .push(unsafe { LINKS.take() }.unwrap_or_default());
+        init.case_mems.push(mems.into_values().collect());
+        self.init_rule = self.gen_rule(&init);
     }
 
-    fn gen_mem(&mut self, mem: &Membrane) -> Vec<IL> {
-        let mut il = Vec::new();
-        il.push(IL::NewMem(mem.id, mem.membrane));
-        if !mem.name.is_empty() {
-            il.push(IL::SetMemName(mem.id, mem.name.clone()));
-        }
-        for process in &mem.process {
-            let mut unit = self.gen_unit(*process);
-            il.append(&mut unit);
-        }
-        for rule_id in &mem.rule_set {
-            let rule = unsafe { RULES.get().unwrap().get(rule_id).unwrap() };
-            let rule_il = gen_rule(rule);
-            self.emit_rule(mem.id, rule_il);
+    /// Generate a rule, and the rule sets of the membranes its body creates.
+    fn gen_rule(&mut self, rule: &Rule) -> RuleIL {
+        let mut rule_gen = rule_gen::RuleGenerator::new(rule, self.rule_set_count);
+        rule_gen.gen();
+        self.rule_set_count += rule_gen.rule_sets.len();
+        for (id, mem) in rule_gen.rule_sets {
+            for rule_id in &mem.rule_set {
+                let rule = unsafe { RULES.get().unwrap().get(rule_id).unwrap() };
+                let rule_il = self.gen_rule(rule);
+                self.emit_rule(id, rule_il);
+            }
         }
-        il
+        rule_gen.il
     }
 }
-
-fn gen_rule(rule: &Rule) -> RuleIL {
-    let mut rule_gen = rule_gen::RuleGenerator::new(rule);
-    rule_gen.gen();
-    rule_gen.il
-}
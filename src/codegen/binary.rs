@@ -0,0 +1,407 @@
+//! Binary encoding of the generated IL.
+//!
+//! All integers are little endian. A file consists of
+//!
+//! - the magic `LMNIL` and a format version byte,
+//! - the file table, each file its path, the offset of its first byte in the source map
+//!   and the offsets in the file of the lines after the first,
+//! - the functor table, each functor its name, its arity and its kind (`0` for a symbol,
+//!   `1` for an integer, `2` for a float), referred to by its index in the table,
+//! - the initial process, a rule with an empty head,
+//! - the rule sets, each its id followed by its rules.
+//!
+//! A rule is its name, its span, its priority as an `i64`,
+//! a flags byte (`1` for `@once`, `2` for `@disabled`), the number of registers it uses,
+//! its history tables and its basic blocks,
+//! the first being the one it starts with.
+//! A span is the start and end offsets of the source text in the source map, which lists the files
+//! one after another, so the file of a span is the last one starting at or before it.
+//! The blocks are followed by their span table,
+//! the list of the instructions generated from the source as the index of the instruction in the
+//! block and its span.
+//! The rules of a rule set are in the order they are tried.
+//! A history table is the list of names of the links it is keyed on.
+//! Lists are prefixed with their length as a `u32`, strings are UTF-8 lists of bytes,
+//! registers, ids and counts are `u32`.
+//! Each instruction starts with its opcode, followed by its operands in declaration order.
+
+use super::{
+    il::{HistoryKey, Instr, Label, MemKind, Reg, IL},
+    rule_gen::RuleIL,
+    ILGenerator,
+};
+use crate::{
+    diagnostic::Span,
+    functor::FunctorKind,
+    parser::rule_parser::{GuardFunction, GuardOperator},
+    source::SourceMap,
+};
+
+pub const MAGIC: &[u8] = b"LMNIL";
+pub const VERSION: u8 = 15;
+
+impl ILGenerator {
+    /// Encode the generated program in the binary format, with the files of `sources` its spans
+    /// point into.
+    pub fn to_binary(&self, sources: &SourceMap) -> Vec<u8> {
+        let mut w = Writer::default();
+        w.buf.extend_from_slice(MAGIC);
+        w.u8(VERSION);
+        w.len(sources.iter().count());
+        for file in sources.iter() {
+            w.str(&file.path.to_string_lossy());
+            w.u32(file.start);
+            let lines: Vec<usize> = file.text.match_indices('\n').map(|(i, _)| i + 1).collect();
+            w.len(lines.len());
+            for line in lines {
+                w.u32(line);
+            }
+        }
+        w.len(self.functors().len());
+        for (_, functor) in self.functors().iter() {
+            w.str(&functor.name);
+            w.u32(functor.arity);
+            w.u8(match functor.kind {
+                FunctorKind::Symbol => 0,
+                FunctorKind::Int => 1,
+                FunctorKind::Float => 2,
+            });
+        }
+        w.rule(&self.init_rule);
+        w.len(self.rule_sets.len());
+        for (id, rules) in &self.rule_sets {
+            w.u32(id.0);
+            w.len(rules.len());
+            for rule in rules {
+                w.rule(rule);
+            }
+        }
+        w.buf
+    }
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.buf.push(value);
+    }
+
+    fn u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("operand does not fit in 32 bits");
+        self.buf.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len);
+    }
+
+    fn str(&mut self, s: &str) {
+        self.len(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn reg(&mut self, reg: Reg) {
+        self.u32(reg.0);
+    }
+
+    fn regs(&mut self, regs: &[Reg]) {
+        self.len(regs.len());
+        for reg in regs {
+            self.reg(*reg);
+        }
+    }
+
+    /// A block followed by its span table.
+    fn spanned_block(&mut self, block: &[Instr]) {
+        self.len(block.len());
+        for instr in block {
+            self.il(&instr.il);
+        }
+        let spans: Vec<_> = block
+            .iter()
+            .enumerate()
+            .filter_map(|(i, instr)| Some((i, instr.span?)))
+            .collect();
+        self.len(spans.len());
+        for (i, span) in spans {
+            self.u32(i);
+            self.span(span);
+        }
+    }
+
+    fn span(&mut self, span: Span) {
+        self.u32(span.start);
+        self.u32(span.end);
+    }
+
+    fn rule(&mut self, rule: &RuleIL) {
+        self.str(&rule.name);
+        self.span(rule.span);
+        self.buf.extend_from_slice(&rule.priority.to_le_bytes());
+        self.u8(rule.once as u8 | (rule.disabled as u8) << 1);
+        self.u32(rule.registers);
+        self.len(rule.histories.len());
+        for table in &rule.histories {
+            self.len(table.keys.len());
+            for key in &table.keys {
+                self.str(key);
+            }
+        }
+        self.len(rule.blocks.len());
+        for block in &rule.blocks {
+            self.spanned_block(block);
+        }
+    }
+
+    fn il(&mut self, il: &IL) {
+        self.u8(opcode(il));
+        match il {
+            IL::NewAtom { dst, mem, functor } | IL::FindAtom { dst, mem, functor } => {
+                self.reg(*dst);
+                self.reg(*mem);
+                self.u32(functor.0);
+            }
+            IL::NewInt { dst, mem, src } | IL::NewFloat { dst, mem, src } => {
+                self.reg(*dst);
+                self.reg(*mem);
+                self.reg(*src);
+            }
+            IL::NewLink {
+                atom1,
+                port1,
+                atom2,
+                port2,
+                mem,
+            }
+            | IL::ReLink {
+                atom1,
+                port1,
+                atom2,
+                port2,
+                mem,
+            } => {
+                self.reg(*atom1);
+                self.u32(port1.0);
+                self.reg(*atom2);
+                self.u32(port2.0);
+                self.reg(*mem);
+            }
+            IL::LoadRuleSet { mem, rule_set } => {
+                self.reg(*mem);
+                self.u32(rule_set.0);
+            }
+            IL::AlterFunctor { atom, functor } | IL::Func { atom, functor } => {
+                self.reg(*atom);
+                self.u32(functor.0);
+            }
+            IL::NewMem { dst, parent } => {
+                self.reg(*dst);
+                self.reg(*parent);
+            }
+            IL::SetMemName { mem, name } | IL::MemName { mem, name } => {
+                self.reg(*mem);
+                self.str(name);
+            }
+            IL::DerefAtom { dst, src, port } => {
+                self.reg(*dst);
+                self.reg(*src);
+                self.u32(port.0);
+            }
+            IL::Deref {
+                dst,
+                src,
+                src_port,
+                dst_port,
+            } => {
+                self.reg(*dst);
+                self.reg(*src);
+                self.u32(src_port.0);
+                self.u32(dst_port.0);
+            }
+            IL::AnyMem {
+                dst,
+                parent,
+                kind,
+                name,
+            } => {
+                self.reg(*dst);
+                self.reg(*parent);
+                self.u32(match kind {
+                    MemKind::Normal => 0,
+                });
+                match name {
+                    Some(name) => {
+                        self.u8(1);
+                        self.str(name);
+                    }
+                    None => self.u8(0),
+                }
+            }
+            IL::RemoveAtom { atom: a, mem: b }
+            | IL::RemoveGround { atom: a, mem: b }
+            | IL::RemoveMem { mem: a, parent: b }
+            | IL::MoveCells { dst: a, src: b }
+            | IL::EqAtom { lhs: a, rhs: b }
+            | IL::NeqAtom { lhs: a, rhs: b }
+            | IL::EqGround { lhs: a, rhs: b }
+            | IL::NeqGround { lhs: a, rhs: b }
+            | IL::IntToFloat { dst: a, src: b }
+            | IL::FloatToInt { dst: a, src: b } => {
+                self.reg(*a);
+                self.reg(*b);
+            }
+            IL::NAtoms { mem, count } | IL::NMems { mem, count } => {
+                self.reg(*mem);
+                self.u32(*count);
+            }
+            IL::FreeAtom { atom: reg }
+            | IL::NoRules { mem: reg }
+            | IL::FreeMem { mem: reg }
+            | IL::IsInt { atom: reg }
+            | IL::IsFloat { atom: reg }
+            | IL::IsString { atom: reg }
+            | IL::IsUnary { atom: reg }
+            | IL::IsGround { atom: reg } => self.reg(*reg),
+            IL::Not(il) => self.il(il),
+            IL::HistoryCheck { table, keys } | IL::HistoryAdd { table, keys } => {
+                self.u32(table.0);
+                self.len(keys.len());
+                for key in keys {
+                    match key {
+                        HistoryKey::Atom(reg) => {
+                            self.u8(0);
+                            self.reg(*reg);
+                        }
+                        HistoryKey::Value(reg) => {
+                            self.u8(1);
+                            self.reg(*reg);
+                        }
+                    }
+                }
+            }
+            IL::LoadInt { dst, value } => {
+                self.reg(*dst);
+                self.buf.extend_from_slice(&value.to_le_bytes());
+            }
+            IL::LoadFloat { dst, value } => {
+                self.reg(*dst);
+                self.buf.extend_from_slice(&value.to_le_bytes());
+            }
+            IL::IntOp { op, dst, lhs, rhs } | IL::FloatOp { op, dst, lhs, rhs } => {
+                self.u8(operator(op));
+                for operand in [dst, lhs, rhs] {
+                    self.reg(*operand);
+                }
+            }
+            IL::IntCmp { op, lhs, rhs } | IL::FloatCmp { op, lhs, rhs } => {
+                self.u8(operator(op));
+                self.reg(*lhs);
+                self.reg(*rhs);
+            }
+            IL::IntCall { func, dst, args } | IL::FloatCall { func, dst, args } => {
+                self.u8(function(func));
+                self.reg(*dst);
+                self.regs(args);
+            }
+            IL::Branch { target } | IL::Jump { target } => self.u32(target.0),
+            IL::Fail | IL::Commit | IL::Proceed => {}
+            IL::Label(Label::RuleSet(id)) => {
+                self.u8(0);
+                self.u32(id.0);
+            }
+            IL::Label(Label::Rule(id)) => {
+                self.u8(1);
+                self.u32(*id);
+            }
+        }
+    }
+}
+
+fn opcode(il: &IL) -> u8 {
+    match il {
+        IL::NewAtom { .. } => 0x01,
+        IL::NewLink { .. } => 0x02,
+        IL::ReLink { .. } => 0x03,
+        IL::NewMem { .. } => 0x04,
+        IL::SetMemName { .. } => 0x05,
+        IL::LoadRuleSet { .. } => 0x06,
+        IL::NewInt { .. } => 0x07,
+        IL::NewFloat { .. } => 0x08,
+        IL::FindAtom { .. } => 0x10,
+        IL::DerefAtom { .. } => 0x11,
+        IL::RemoveAtom { .. } => 0x12,
+        IL::FreeAtom { .. } => 0x13,
+        IL::AlterFunctor { .. } => 0x14,
+        IL::Deref { .. } => 0x15,
+        IL::Func { .. } => 0x16,
+        IL::EqAtom { .. } => 0x17,
+        IL::NeqAtom { .. } => 0x18,
+        IL::RemoveGround { .. } => 0x19,
+        IL::AnyMem { .. } => 0x20,
+        IL::NAtoms { .. } => 0x21,
+        IL::NMems { .. } => 0x22,
+        IL::NoRules { .. } => 0x23,
+        IL::MemName { .. } => 0x24,
+        IL::RemoveMem { .. } => 0x25,
+        IL::FreeMem { .. } => 0x26,
+        IL::MoveCells { .. } => 0x27,
+        IL::IsInt { .. } => 0x30,
+        IL::IsFloat { .. } => 0x31,
+        IL::IsString { .. } => 0x32,
+        IL::IsUnary { .. } => 0x33,
+        IL::IsGround { .. } => 0x34,
+        IL::EqGround { .. } => 0x35,
+        IL::NeqGround { .. } => 0x36,
+        IL::Not(..) => 0x37,
+        IL::HistoryCheck { .. } => 0x38,
+        IL::HistoryAdd { .. } => 0x39,
+        IL::LoadInt { .. } => 0x40,
+        IL::LoadFloat { .. } => 0x41,
+        IL::IntOp { .. } => 0x42,
+        IL::FloatOp { .. } => 0x43,
+        IL::IntCmp { .. } => 0x44,
+        IL::FloatCmp { .. } => 0x45,
+        IL::IntToFloat { .. } => 0x46,
+        IL::FloatToInt { .. } => 0x47,
+        IL::IntCall { .. } => 0x48,
+        IL::FloatCall { .. } => 0x49,
+        IL::Branch { .. } => 0x50,
+        IL::Jump { .. } => 0x51,
+        IL::Fail => 0x52,
+        IL::Commit => 0x53,
+        IL::Proceed => 0x54,
+        IL::Label(..) => 0xf0,
+    }
+}
+
+fn operator(op: &GuardOperator) -> u8 {
+    match op.base() {
+        GuardOperator::Add => 0,
+        GuardOperator::Sub => 1,
+        GuardOperator::Mul => 2,
+        GuardOperator::Div => 3,
+        GuardOperator::Mod => 4,
+        GuardOperator::Eq => 5,
+        GuardOperator::Neq => 6,
+        GuardOperator::Lt => 7,
+        GuardOperator::Le => 8,
+        GuardOperator::Gt => 9,
+        GuardOperator::Ge => 10,
+        op => unreachable!("`{}` is not an instruction operator", op),
+    }
+}
+
+fn function(func: &GuardFunction) -> u8 {
+    match func {
+        GuardFunction::Int => 0,
+        GuardFunction::Float => 1,
+        GuardFunction::Abs => 2,
+        GuardFunction::Min => 3,
+        GuardFunction::Max => 4,
+        GuardFunction::Sqrt => 5,
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+
+use crate::parser::{
+    data::Symbol,
+    rule_parser::{
+        Case, GuardFunction, GuardNode, GuardOperator, GuardTypes, MemPredicate, TempVar, Type,
+    },
+};
+
+use super::{
+    il::{HistoryId, HistoryKey, Port, Reg, IL},
+    rule_gen::{HistoryTable, RuleGenerator},
+};
+
+/// State of the clause whose guard is being generated.
+#[derive(Debug, Default)]
+pub(super) struct Clause<'a> {
+    pub(super) types: GuardTypes,
+    /// Link or process context -> register holding the atom it points to
+    pub(super) symbols: HashMap<Symbol, Reg>,
+    temp_vars: &'a [TempVar],
+    /// Temporary variable -> register holding its value, once it is computed
+    pub(super) vars: Vec<Option<Reg>>,
+    pub(super) il: Vec<IL>,
+    /// Instructions to run once this clause is chosen.
+    pub(super) commit: Vec<IL>,
+}
+
+impl RuleGenerator<'_> {
+    /// Generate the checks of one clause of the guard of `case`, computing its temporary
+    /// variables on the way.
+    ///
+    /// The returned clause also holds the instructions to run before the body, such as recording
+    /// `uniq` histories, and the registers of the symbols and variables the body may use.
+    pub(super) fn gen_guard<'c>(
+        &mut self,
+        case: &'c Case,
+        clause_id: usize,
+        clause: &[GuardNode],
+    ) -> Clause<'c> {
+        let mut ctx = Clause {
+            types: case.guard_types.get(clause_id).cloned().unwrap_or_default(),
+            temp_vars: &case.vars,
+            vars: vec![None; case.vars.len()],
+            ..Default::default()
+        };
+        for cond in clause {
+            self.gen_condition(cond, &mut ctx);
+        }
+        // the variables no condition uses
+        for i in 0..case.vars.len() {
+            self.gen_expr(&GuardNode::Var(i), &mut ctx);
+        }
+        ctx
+    }
+
+    /// Load the atom a link or process context of the head points to.
+    ///
+    /// If its type is known, it is checked right away,
+    /// so later type checks of the same symbol are not emitted again.
+    fn bind(&mut self, symbol: Symbol, ctx: &mut Clause<'_>) -> Reg {
+        if let Some(reg) = ctx.symbols.get(&symbol) {
+            return *reg;
+        }
+        let (src, port) = self
+            .head_endpoint(symbol)
+            .unwrap_or_else(|| panic!("{:?} is not connected to a matched atom", symbol));
+        let reg = self.new_register();
+        ctx.il.push(IL::DerefAtom {
+            dst: reg,
+            src,
+            port,
+        });
+        if let Some(ty) = ctx.types.symbols.get(&symbol) {
+            ctx.il.extend(type_check(*ty, reg));
+        }
+        ctx.symbols.insert(symbol, reg);
+        reg
+    }
+
+    /// Find the register of a matched atom and the port the symbol is connected to.
+    pub(super) fn head_endpoint(&self, symbol: Symbol) -> Option<(Reg, Port)> {
+        match symbol {
+            Symbol::Link(id) => {
+                let link = &self.rule.links[&id];
+                [link.link1, link.link2]
+                    .into_iter()
+                    .flatten()
+                    .find_map(|(atom, port)| match atom {
+                        Symbol::Atom(atom) => {
+                            self.atom_regs.get(&atom).map(|reg| (*reg, Port(port)))
+                        }
+                        _ => None,
+                    })
+            }
+            Symbol::ProcContext(_) => self.rule.atoms.iter().find_map(|atom| {
+                let port = atom.links.iter().position(|s| *s == symbol)?;
+                self.atom_regs.get(&atom.id).map(|reg| (*reg, Port(port)))
+            }),
+            _ => None,
+        }
+    }
+
+    fn gen_condition(&mut self, node: &GuardNode, ctx: &mut Clause<'_>) {
+        match node {
+            GuardNode::TypeConstraint(Type::Uniq, args, _) => {
+                let (table, keys) = self.history(args, ctx);
+                ctx.il.push(IL::HistoryCheck {
+                    table,
+                    keys: keys.clone(),
+                });
+                ctx.commit.push(IL::HistoryAdd { table, keys });
+            }
+            GuardNode::TypeConstraint(ty, args, _) => {
+                for arg in args {
+                    let known = ctx.types.symbols.get(arg) == Some(ty);
+                    let reg = self.bind(*arg, ctx);
+                    // a symbol of known type was checked when it was loaded
+                    if !known {
+                        ctx.il.extend(type_check(*ty, reg));
+                    }
+                }
+            }
+            GuardNode::MemPredicate(predicate, mem, _) => {
+                ctx.il.push(mem_check(predicate, self.mem_regs[mem]));
+            }
+            // only type checks of a single symbol and membrane predicates
+            // are left negated by `GuardNode::clauses`
+            GuardNode::Not(check, _) => match check.as_ref() {
+                // the rule has already been applied to these atoms
+                GuardNode::TypeConstraint(Type::Uniq, args, _) => {
+                    let (table, keys) = self.history(args, ctx);
+                    let check = IL::HistoryCheck { table, keys };
+                    ctx.il.push(IL::Not(Box::new(check)));
+                }
+                GuardNode::TypeConstraint(ty, args, _) => {
+                    let reg = self.bind(args[0], ctx);
+                    if let Some(check) = type_check(*ty, reg) {
+                        ctx.il.push(IL::Not(Box::new(check)));
+                    }
+                }
+                GuardNode::MemPredicate(predicate, mem, _) => {
+                    let check = mem_check(predicate, self.mem_regs[mem]);
+                    ctx.il.push(IL::Not(Box::new(check)));
+                }
+                _ => unreachable!("not a negated type check: {:?}", check),
+            },
+            GuardNode::Operation(op, lhs, rhs, _) if op.is_comparison() => {
+                let ty = expr_type(lhs, ctx).or(expr_type(rhs, ctx));
+                let (lhs, rhs) = (self.gen_expr(lhs, ctx), self.gen_expr(rhs, ctx));
+                let op = op.base();
+                ctx.il.push(match ty {
+                    Some(Type::Float) => IL::FloatCmp { op, lhs, rhs },
+                    Some(Type::Ground) if op == GuardOperator::Eq => IL::EqGround { lhs, rhs },
+                    Some(Type::Ground) => IL::NeqGround { lhs, rhs },
+                    _ => IL::IntCmp { op, lhs, rhs },
+                });
+            }
+            _ => unreachable!("not a condition: {:?}", node),
+        }
+    }
+
+    /// Find the history table of `uniq` over `args`, shared by all cases of the rule,
+    /// and load the atoms that make up its entries.
+    fn history(&mut self, args: &[Symbol], ctx: &mut Clause<'_>) -> (HistoryId, Vec<HistoryKey>) {
+        let names: Vec<String> = args
+            .iter()
+            .map(|arg| match arg {
+                Symbol::Link(id) => self.rule.links[id].name.clone(),
+                Symbol::ProcContext(id) => format!("${}", self.rule.procs[*id].name),
+                _ => unreachable!(),
+            })
+            .collect();
+        let table = match self.il.histories.iter().position(|t| t.keys == names) {
+            Some(table) => HistoryId(table),
+            None => {
+                self.il.histories.push(HistoryTable { keys: names });
+                HistoryId(self.il.histories.len() - 1)
+            }
+        };
+        let keys = args
+            .iter()
+            .map(|arg| {
+                let reg = self.bind(*arg, ctx);
+                // data atoms are told apart by their values
+                match ctx.types.symbols.get(arg) {
+                    Some(_) => HistoryKey::Value(reg),
+                    None => HistoryKey::Atom(reg),
+                }
+            })
+            .collect();
+        (table, keys)
+    }
+
+    fn gen_expr(&mut self, node: &GuardNode, ctx: &mut Clause<'_>) -> Reg {
+        match node {
+            GuardNode::IntValue(value) => {
+                let dst = self.new_register();
+                ctx.il.push(IL::LoadInt { dst, value: *value });
+                dst
+            }
+            GuardNode::FloatValue(value) => {
+                let dst = self.new_register();
+                ctx.il.push(IL::LoadFloat { dst, value: *value });
+                dst
+            }
+            GuardNode::Value(symbol) => self.bind(*symbol, ctx),
+            // computed where first used, as a condition may come before a `with` clause
+            // it depends on; its expression only uses earlier variables
+            GuardNode::Var(i) => match ctx.vars[*i] {
+                Some(reg) => reg,
+                None => {
+                    let reg = self.gen_expr(&ctx.temp_vars[*i].value, ctx);
+                    ctx.vars[*i] = Some(reg);
+                    reg
+                }
+            },
+            GuardNode::Operation(op, lhs, rhs, _) if op.is_arithmetic() => {
+                let ty = expr_type(node, ctx);
+                let (lhs, rhs) = (self.gen_expr(lhs, ctx), self.gen_expr(rhs, ctx));
+                let (op, dst) = (op.base(), self.new_register());
+                ctx.il.push(match ty {
+                    Some(Type::Float) => IL::FloatOp { op, dst, lhs, rhs },
+                    _ => IL::IntOp { op, dst, lhs, rhs },
+                });
+                dst
+            }
+            GuardNode::Call(func, args, _) => {
+                let ty = expr_type(&args[0], ctx);
+                let args: Vec<Reg> = args.iter().map(|arg| self.gen_expr(arg, ctx)).collect();
+                match (func, ty) {
+                    // converting a value to its own type does nothing
+                    (GuardFunction::Int, Some(Type::Int))
+                    | (GuardFunction::Float, Some(Type::Float)) => args[0],
+                    (GuardFunction::Int, _) => {
+                        let dst = self.new_register();
+                        ctx.il.push(IL::FloatToInt { dst, src: args[0] });
+                        dst
+                    }
+                    (GuardFunction::Float, _) => {
+                        let dst = self.new_register();
+                        ctx.il.push(IL::IntToFloat { dst, src: args[0] });
+                        dst
+                    }
+                    (_, Some(Type::Float)) => {
+                        let dst = self.new_register();
+                        let func = *func;
+                        ctx.il.push(IL::FloatCall { func, dst, args });
+                        dst
+                    }
+                    _ => {
+                        let dst = self.new_register();
+                        let func = *func;
+                        ctx.il.push(IL::IntCall { func, dst, args });
+                        dst
+                    }
+                }
+            }
+            _ => unreachable!("not an expression: {:?}", node),
+        }
+    }
+}
+
+/// The instruction checking that the atom in `reg` has type `ty`.
+fn type_check(ty: Type, atom: Reg) -> Option<IL> {
+    match ty {
+        Type::Int => Some(IL::IsInt { atom }),
+        Type::Float => Some(IL::IsFloat { atom }),
+        Type::String => Some(IL::IsString { atom }),
+        Type::Unary => Some(IL::IsUnary { atom }),
+        Type::Ground => Some(IL::IsGround { atom }),
+        Type::Uniq => None,
+    }
+}
+
+/// The instruction checking a predicate on the membrane in `reg`.
+fn mem_check(predicate: &MemPredicate, mem: Reg) -> IL {
+    match predicate {
+        MemPredicate::NAtoms(count) => IL::NAtoms { mem, count: *count },
+        MemPredicate::NMems(count) => IL::NMems { mem, count: *count },
+        MemPredicate::Name(name) => IL::MemName {
+            mem,
+            name: name.clone(),
+        },
+        MemPredicate::NoRules => IL::NoRules { mem },
+    }
+}
+
+/// Type of an arithmetic expression, as inferred by the analysis.
+fn expr_type(node: &GuardNode, ctx: &Clause<'_>) -> Option<Type> {
+    match node {
+        GuardNode::IntValue(_) => Some(Type::Int),
+        GuardNode::FloatValue(_) => Some(Type::Float),
+        GuardNode::Value(symbol) => ctx.types.symbols.get(symbol).copied(),
+        GuardNode::Var(i) => ctx.types.vars.get(*i).copied().flatten(),
+        GuardNode::Operation(op, lhs, rhs, _) if op.is_arithmetic() => {
+            expr_type(lhs, ctx).or(expr_type(rhs, ctx))
+        }
+        GuardNode::Call(GuardFunction::Int, _, _) => Some(Type::Int),
+        GuardNode::Call(GuardFunction::Float, _, _) => Some(Type::Float),
+        GuardNode::Call(_, args, _) => args.iter().find_map(|arg| expr_type(arg, ctx)),
+        _ => None,
+    }
+}
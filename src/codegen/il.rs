@@ -1,138 +1,858 @@
 use std::fmt::Display;
 
-use crate::parser::data::Link;
+use crate::{
+    diagnostic::Span,
+    functor::{FunctorId, FunctorTable},
+    parser::{
+        data::{AtomId, Link},
+        rule_parser::{GuardFunction, GuardOperator},
+    },
+};
+
+/// A register of a rule, holding an atom, a membrane or a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Reg(pub usize);
+
+impl Display for Reg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An argument position of an atom, starting from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Port(pub usize);
+
+impl Display for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A basic block of a rule, referred to by its index in the rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct BlockId(pub usize);
+
+impl Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A rule set, numbered in the order the instructions loading rule sets are generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RuleSetId(pub usize);
+
+impl Display for RuleSetId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A history table of a rule, referred to by its index in the rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct HistoryId(pub usize);
+
+impl Display for HistoryId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What kind of membrane `AnyMem` matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemKind {
+    /// Any membrane, the only kind written in the language so far.
+    Normal,
+}
+
+impl Display for MemKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemKind::Normal => write!(f, "0"),
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
 pub enum Label {
-    RuleSet(usize),
+    RuleSet(RuleSetId),
     Rule(usize),
 }
 
+/// How an entry of a history table identifies a matched atom.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HistoryKey {
+    /// The identity of the atom in the register.
+    Atom(Reg),
+    /// The value of the ground structure in the register.
+    Value(Reg),
+}
+
+impl Display for HistoryKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HistoryKey::Atom(reg) => write!(f, "id({})", reg),
+            HistoryKey::Value(reg) => write!(f, "val({})", reg),
+        }
+    }
+}
+
+/// An instruction of the IL.
+///
+/// In a rule, register 0 holds the membrane the rule is applied in.
+///
+/// A check that does not hold fails the rule, which goes back to the last choice:
+/// the next candidate of the last `FindAtom` or `AnyMem`, or the instruction after the last `Branch`.
+/// The rule is not applied if no choice is left.
 #[derive(Debug, Clone)]
 pub enum IL {
-    /// NewAtom(atom_id, mem_id, name)
-    ///
-    /// Creates a new atom with the given id, in the given membrane, with the given name and arity.
-    NewAtom(
-        usize,  /* atom id */
-        usize,  /* membrane id */
-        String, /* name_funtor */
-        usize,  /* arity */
-    ),
-
-    NewLink(
-        usize, /* atom 1 id */
-        usize, /* pos 1 id */
-        usize, /* atom 2 id */
-        usize, /* pos 2 id */
-        usize, /* mem id */
-    ),
-    ReLink(
-        usize, /* link id */
-        usize, /* atom id */
-        usize, /* atom id */
-    ),
-
-    NewMem(usize /* mem id */, usize /* parent mem id */),
-    SetMemName(usize /* mem id */, String /* name */),
-
-    FindAtom(
-        usize,  /* to register */
-        usize,  /* mem id */
-        String, /* name */
-        usize,  /* arity */
-    ),
-    DerefAtom(
-        usize, /* to register */
-        usize, /* from register */
-        usize, /* position */
-    ),
-    RemoveAtom(usize /* register id */, usize /* parent mem id */),
-    FreeAtom(usize /* register id */),
-
-    AnyMem(
-        usize,          /* register id */
-        usize,          /* parent mem id */
-        usize,          /* mem type */
-        Option<String>, /* name */
-    ),
-    NAtoms(usize /* register id */, usize /* count */),
-    NMems(usize /* register id */, usize /* count */),
-    NoRules(usize /* register id */),
-    RemoveMem(usize /* register id */, usize /* parent mem id */),
-    FreeMem(usize /* register id */),
-
-    IsInt(usize /* register id */),
-    IsFloat(usize /* register id */),
-    IsUnary(usize /* register id */),
-    IsGround(usize /* register id */),
+    /// Creates an atom with the given functor in the membrane `mem`.
+    NewAtom {
+        dst: Reg,
+        mem: Reg,
+        functor: FunctorId,
+    },
+    /// Creates an integer atom holding the value in `src` in the membrane `mem`.
+    NewInt {
+        dst: Reg,
+        mem: Reg,
+        src: Reg,
+    },
+    NewFloat {
+        dst: Reg,
+        mem: Reg,
+        src: Reg,
+    },
+    /// Connects `port1` of `atom1` and `port2` of `atom2`.
+    NewLink {
+        atom1: Reg,
+        port1: Port,
+        atom2: Reg,
+        port2: Port,
+        mem: Reg,
+    },
+    /// Connects `port1` of `atom1` to what `port2` of `atom2` is connected to.
+    ReLink {
+        atom1: Reg,
+        port1: Port,
+        atom2: Reg,
+        port2: Port,
+        mem: Reg,
+    },
+
+    NewMem {
+        dst: Reg,
+        parent: Reg,
+    },
+    SetMemName {
+        mem: Reg,
+        name: String,
+    },
+    /// Adds the rules of a rule set to the membrane `mem`.
+    LoadRuleSet {
+        mem: Reg,
+        rule_set: RuleSetId,
+    },
+
+    /// Finds an atom with the given functor in the membrane `mem`,
+    /// trying the instructions after it with each such atom in turn.
+    FindAtom {
+        dst: Reg,
+        mem: Reg,
+        functor: FunctorId,
+    },
+    /// Loads the atom `port` of `src` is connected to.
+    DerefAtom {
+        dst: Reg,
+        src: Reg,
+        port: Port,
+    },
+    /// Loads the atom `src_port` of `src` is connected to,
+    /// failing unless the link ends at its port `dst_port`.
+    Deref {
+        dst: Reg,
+        src: Reg,
+        src_port: Port,
+        dst_port: Port,
+    },
+    /// Fails if the atom does not have the given functor.
+    Func {
+        atom: Reg,
+        functor: FunctorId,
+    },
+    /// Fails if the two registers do not hold the same atom.
+    EqAtom {
+        lhs: Reg,
+        rhs: Reg,
+    },
+    NeqAtom {
+        lhs: Reg,
+        rhs: Reg,
+    },
+    RemoveAtom {
+        atom: Reg,
+        mem: Reg,
+    },
+    /// Removes the ground structure the atom in `atom` belongs to from the membrane `mem`.
+    RemoveGround {
+        atom: Reg,
+        mem: Reg,
+    },
+    FreeAtom {
+        atom: Reg,
+    },
+    /// Changes the functor of `atom` to one of the same arity, keeping its links.
+    AlterFunctor {
+        atom: Reg,
+        functor: FunctorId,
+    },
+
+    /// Finds a membrane of the given kind, and name if any, in `parent`,
+    /// trying the instructions after it with each such membrane in turn.
+    AnyMem {
+        dst: Reg,
+        parent: Reg,
+        kind: MemKind,
+        name: Option<String>,
+    },
+    /// Fails if the membrane does not have exactly `count` atoms.
+    NAtoms {
+        mem: Reg,
+        count: usize,
+    },
+    /// Fails if the membrane does not have exactly `count` child membranes.
+    NMems {
+        mem: Reg,
+        count: usize,
+    },
+    NoRules {
+        mem: Reg,
+    },
+    /// Fails if the membrane does not have the given name.
+    MemName {
+        mem: Reg,
+        name: String,
+    },
+    RemoveMem {
+        mem: Reg,
+        parent: Reg,
+    },
+    /// Moves the atoms and membranes left in the removed membrane `src` into the membrane `dst`.
+    MoveCells {
+        dst: Reg,
+        src: Reg,
+    },
+    FreeMem {
+        mem: Reg,
+    },
+
+    IsInt {
+        atom: Reg,
+    },
+    IsFloat {
+        atom: Reg,
+    },
+    IsString {
+        atom: Reg,
+    },
+    IsUnary {
+        atom: Reg,
+    },
+    IsGround {
+        atom: Reg,
+    },
+    /// Fails if the ground structures the two registers point to are not equal.
+    EqGround {
+        lhs: Reg,
+        rhs: Reg,
+    },
+    NeqGround {
+        lhs: Reg,
+        rhs: Reg,
+    },
+    /// Fails if the wrapped check succeeds.
+    Not(Box<IL>),
+
+    LoadInt {
+        dst: Reg,
+        value: i64,
+    },
+    LoadFloat {
+        dst: Reg,
+        value: f64,
+    },
+    /// Arithmetic on the values in two registers.
+    IntOp {
+        op: GuardOperator,
+        dst: Reg,
+        lhs: Reg,
+        rhs: Reg,
+    },
+    FloatOp {
+        op: GuardOperator,
+        dst: Reg,
+        lhs: Reg,
+        rhs: Reg,
+    },
+    /// Fails if the comparison between the values in two registers does not hold.
+    IntCmp {
+        op: GuardOperator,
+        lhs: Reg,
+        rhs: Reg,
+    },
+    FloatCmp {
+        op: GuardOperator,
+        lhs: Reg,
+        rhs: Reg,
+    },
+    /// Fails if the combination of atoms was already recorded in the history table.
+    HistoryCheck {
+        table: HistoryId,
+        keys: Vec<HistoryKey>,
+    },
+    /// Records the combination of atoms in the history table.
+    HistoryAdd {
+        table: HistoryId,
+        keys: Vec<HistoryKey>,
+    },
+
+    IntToFloat {
+        dst: Reg,
+        src: Reg,
+    },
+    FloatToInt {
+        dst: Reg,
+        src: Reg,
+    },
+    /// Calls a built-in math function on the values in the argument registers.
+    IntCall {
+        func: GuardFunction,
+        dst: Reg,
+        args: Vec<Reg>,
+    },
+    FloatCall {
+        func: GuardFunction,
+        dst: Reg,
+        args: Vec<Reg>,
+    },
+
+    /// Runs the block `target`, going on with the next instruction if it fails.
+    Branch {
+        target: BlockId,
+    },
+    /// Goes on with the block `target`.
+    Jump {
+        target: BlockId,
+    },
+    /// Fails the rule.
+    Fail,
+    /// Commits to the current match, nothing is tried again after this.
+    Commit,
+    /// Ends the application of the rule.
+    Proceed,
 
     Label(Label),
 }
 
+/// Without the functor table, functors are printed as their ids.
 impl Display for IL {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            IL::NewAtom(atom_id, mem_id, name, arity) => {
-                write!(f, "new_atom\t{}, {}, {}, {}", atom_id, mem_id, name, arity)
+            IL::NewAtom { dst, mem, functor } => {
+                write!(f, "new_atom\t{}, {}, {}", dst, mem, functor)
             }
-            IL::NewLink(atom1_id, pos1_id, atom2_id, pos2_id, mem_id) => write!(
+            IL::NewInt { dst, mem, src } => write!(f, "new_int \t{}, {}, {}", dst, mem, src),
+            IL::NewFloat { dst, mem, src } => write!(f, "new_float\t{}, {}, {}", dst, mem, src),
+            IL::NewLink {
+                atom1,
+                port1,
+                atom2,
+                port2,
+                mem,
+            } => write!(
                 f,
                 "new_link\t{}, {}, {}, {}, {}",
-                atom1_id, pos1_id, atom2_id, pos2_id, mem_id
+                atom1, port1, atom2, port2, mem
             ),
-            IL::ReLink(link_id, atom1_id, atom2_id) => {
-                write!(f, "relink\t{}, {}, {}", link_id, atom1_id, atom2_id)
-            }
-            IL::NewMem(mem_id, parent_mem_id) => {
-                write!(f, "new_mem \t{}, {}", mem_id, parent_mem_id)
-            }
-            IL::SetMemName(mem_id, name) => write!(f, "set_mem_name\t{}, {}", mem_id, name),
-            IL::FindAtom(to_register, mem_id, name, arity) => write!(
+            IL::ReLink {
+                atom1,
+                port1,
+                atom2,
+                port2,
+                mem,
+            } => write!(
                 f,
-                "find_atom\t{}, {}, {}, {}",
-                to_register, mem_id, name, arity
+                "relink\t{}, {}, {}, {}, {}",
+                atom1, port1, atom2, port2, mem
             ),
-            IL::DerefAtom(to_register, from_register, position) => write!(
-                f,
-                "deref_atom\t{}, {}, {}",
-                to_register, from_register, position
-            ),
-            IL::RemoveAtom(register_id, parent_mem_id) => {
-                write!(f, "remove_atom\t{}, {}", register_id, parent_mem_id)
+            IL::NewMem { dst, parent } => write!(f, "new_mem \t{}, {}", dst, parent),
+            IL::SetMemName { mem, name } => write!(f, "set_mem_name\t{}, {}", mem, name),
+            IL::LoadRuleSet { mem, rule_set } => {
+                write!(f, "load_rule_set\t{}, {}", mem, rule_set)
+            }
+            IL::FindAtom { dst, mem, functor } => {
+                write!(f, "find_atom\t{}, {}, {}", dst, mem, functor)
+            }
+            IL::DerefAtom { dst, src, port } => {
+                write!(f, "deref_atom\t{}, {}, {}", dst, src, port)
             }
-            IL::FreeAtom(register_id) => write!(f, "free_atom\t{}", register_id),
-            IL::AnyMem(register_id, parent_mem_id, mem_type, name) => write!(
+            IL::Deref {
+                dst,
+                src,
+                src_port,
+                dst_port,
+            } => write!(f, "deref   \t{}, {}, {}, {}", dst, src, src_port, dst_port),
+            IL::Func { atom, functor } => write!(f, "func    \t{}, {}", atom, functor),
+            IL::EqAtom { lhs, rhs } => write!(f, "eqatom  \t{}, {}", lhs, rhs),
+            IL::NeqAtom { lhs, rhs } => write!(f, "neqatom \t{}, {}", lhs, rhs),
+            IL::RemoveAtom { atom, mem } => write!(f, "remove_atom\t{}, {}", atom, mem),
+            IL::RemoveGround { atom, mem } => write!(f, "remove_ground\t{}, {}", atom, mem),
+            IL::FreeAtom { atom } => write!(f, "free_atom\t{}", atom),
+            IL::AlterFunctor { atom, functor } => {
+                write!(f, "alter_functor\t{}, {}", atom, functor)
+            }
+            IL::AnyMem {
+                dst,
+                parent,
+                kind,
+                name,
+            } => write!(
                 f,
                 "any_mem \t{}, {}, {}, {}",
-                register_id,
-                parent_mem_id,
-                mem_type,
-                name.as_ref().unwrap_or(&"".to_owned())
+                dst,
+                parent,
+                kind,
+                name.as_deref().unwrap_or("")
             ),
-            IL::NAtoms(register_id, count) => write!(f, "natoms  \t{}, {}", register_id, count),
-            IL::NMems(register_id, count) => write!(f, "nmems   \t{}, {}", register_id, count),
-            IL::NoRules(_) => todo!(),
-            IL::RemoveMem(register_id, mem) => write!(f, "remove_mem\t{}, {}", register_id, mem),
-            IL::FreeMem(_) => todo!(),
+            IL::NAtoms { mem, count } => write!(f, "natoms  \t{}, {}", mem, count),
+            IL::NMems { mem, count } => write!(f, "nmems   \t{}, {}", mem, count),
+            IL::NoRules { mem } => write!(f, "norules \t{}", mem),
+            IL::MemName { mem, name } => write!(f, "memname \t{}, {}", mem, name),
+            IL::RemoveMem { mem, parent } => write!(f, "remove_mem\t{}, {}", mem, parent),
+            IL::MoveCells { dst, src } => write!(f, "move_cells\t{}, {}", dst, src),
+            IL::FreeMem { mem } => write!(f, "free_mem\t{}", mem),
+            IL::Branch { target } => write!(f, "branch  \t{}", target),
+            IL::Jump { target } => write!(f, "jump    \t{}", target),
+            IL::Fail => write!(f, "fail"),
+            IL::Commit => write!(f, "commit"),
+            IL::Proceed => write!(f, "proceed"),
             IL::Label(l) => match l {
                 Label::RuleSet(id) => write!(f, "rule_set\t{}", id),
                 Label::Rule(id) => write!(f, "rule\t{}", id),
             },
-            IL::IsInt(_) => todo!(),
-            IL::IsFloat(_) => todo!(),
-            IL::IsUnary(_) => todo!(),
-            IL::IsGround(_) => todo!(),
+            IL::IsInt { atom } => write!(f, "isint   \t{}", atom),
+            IL::IsFloat { atom } => write!(f, "isfloat \t{}", atom),
+            IL::IsString { atom } => write!(f, "isstring\t{}", atom),
+            IL::IsUnary { atom } => write!(f, "isunary \t{}", atom),
+            IL::IsGround { atom } => write!(f, "isground\t{}", atom),
+            IL::EqGround { lhs, rhs } => write!(f, "eqground\t{}, {}", lhs, rhs),
+            IL::NeqGround { lhs, rhs } => write!(f, "neqground\t{}, {}", lhs, rhs),
+            IL::Not(il) => write!(f, "not {}", il),
+            IL::LoadInt { dst, value } => write!(f, "load_int\t{}, {}", dst, value),
+            IL::LoadFloat { dst, value } => write!(f, "load_float\t{}, {:?}", dst, value),
+            IL::IntOp { op, dst, lhs, rhs } => {
+                write!(f, "{:8}\t{}, {}, {}", mnemonic("i", op), dst, lhs, rhs)
+            }
+            IL::FloatOp { op, dst, lhs, rhs } => {
+                write!(f, "{:8}\t{}, {}, {}", mnemonic("f", op), dst, lhs, rhs)
+            }
+            IL::IntCmp { op, lhs, rhs } => write!(f, "{:8}\t{}, {}", mnemonic("i", op), lhs, rhs),
+            IL::FloatCmp { op, lhs, rhs } => {
+                write!(f, "{:8}\t{}, {}", mnemonic("f", op), lhs, rhs)
+            }
+            IL::HistoryCheck { table, keys } => {
+                write!(f, "history_check\t{}, {}", table, join(keys))
+            }
+            IL::HistoryAdd { table, keys } => {
+                write!(f, "history_add\t{}, {}", table, join(keys))
+            }
+            IL::IntToFloat { dst, src } => write!(f, "int2float\t{}, {}", dst, src),
+            IL::FloatToInt { dst, src } => write!(f, "float2int\t{}, {}", dst, src),
+            IL::IntCall { func, dst, args } => {
+                write!(f, "{:8}\t{}, {}", format!("i{}", func), dst, join(args))
+            }
+            IL::FloatCall { func, dst, args } => {
+                write!(f, "{:8}\t{}, {}", format!("f{}", func), dst, join(args))
+            }
+        }
+    }
+}
+
+/// An instruction printed with the names and arities of its functors.
+pub struct Resolved<'a> {
+    il: &'a IL,
+    functors: &'a FunctorTable,
+}
+
+impl Display for Resolved<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.il {
+            IL::NewAtom { dst, mem, functor } => {
+                let functor = self.functors.get(*functor);
+                write!(
+                    f,
+                    "new_atom\t{}, {}, {}, {}",
+                    dst, mem, functor.name, functor.arity
+                )
+            }
+            IL::FindAtom { dst, mem, functor } => {
+                let functor = self.functors.get(*functor);
+                write!(
+                    f,
+                    "find_atom\t{}, {}, {}, {}",
+                    dst, mem, functor.name, functor.arity
+                )
+            }
+            IL::AlterFunctor { atom, functor } => {
+                let functor = self.functors.get(*functor);
+                write!(
+                    f,
+                    "alter_functor\t{}, {}, {}",
+                    atom, functor.name, functor.arity
+                )
+            }
+            IL::Func { atom, functor } => {
+                let functor = self.functors.get(*functor);
+                write!(f, "func    \t{}, {}, {}", atom, functor.name, functor.arity)
+            }
+            il => il.fmt(f),
         }
     }
 }
 
+/// Name of a guard instruction, `prefix` tells the type of its operands.
+fn mnemonic(prefix: &str, op: &GuardOperator) -> String {
+    let name = match op.base() {
+        GuardOperator::Add => "add",
+        GuardOperator::Sub => "sub",
+        GuardOperator::Mul => "mul",
+        GuardOperator::Div => "div",
+        GuardOperator::Mod => "mod",
+        GuardOperator::Eq => "eq",
+        GuardOperator::Neq => "ne",
+        GuardOperator::Lt => "lt",
+        GuardOperator::Le => "le",
+        GuardOperator::Gt => "gt",
+        GuardOperator::Ge => "ge",
+        _ => unreachable!(),
+    };
+    format!("{}{}", prefix, name)
+}
+
+fn join(operands: &[impl Display]) -> String {
+    operands
+        .iter()
+        .map(|r| r.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 impl IL {
-    pub fn new_link(link: &Link, mem_id: usize) -> Self {
+    /// Whether this instruction ends a block, the instructions after it are never run.
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, IL::Jump { .. } | IL::Fail | IL::Proceed)
+    }
+
+    /// Whether this instruction is a choice, tried again with its next candidate
+    /// when an instruction after it fails.
+    pub fn is_choice(&self) -> bool {
+        matches!(self, IL::FindAtom { .. } | IL::AnyMem { .. })
+    }
+
+    /// Whether this instruction can fail the rule.
+    pub fn can_fail(&self) -> bool {
+        matches!(
+            self,
+            IL::FindAtom { .. }
+                | IL::Deref { .. }
+                | IL::Func { .. }
+                | IL::EqAtom { .. }
+                | IL::NeqAtom { .. }
+                | IL::AnyMem { .. }
+                | IL::NAtoms { .. }
+                | IL::NMems { .. }
+                | IL::NoRules { .. }
+                | IL::MemName { .. }
+                | IL::IsInt { .. }
+                | IL::IsFloat { .. }
+                | IL::IsString { .. }
+                | IL::IsUnary { .. }
+                | IL::IsGround { .. }
+                | IL::EqGround { .. }
+                | IL::NeqGround { .. }
+                | IL::Not(_)
+                | IL::IntCmp { .. }
+                | IL::FloatCmp { .. }
+                | IL::HistoryCheck { .. }
+                | IL::Fail
+        )
+    }
+
+    /// The register this instruction writes, if any.
+    pub fn writes(&self) -> Option<Reg> {
+        match self {
+            IL::NewAtom { dst, .. }
+            | IL::NewInt { dst, .. }
+            | IL::NewFloat { dst, .. }
+            | IL::NewMem { dst, .. }
+            | IL::FindAtom { dst, .. }
+            | IL::DerefAtom { dst, .. }
+            | IL::Deref { dst, .. }
+            | IL::AnyMem { dst, .. }
+            | IL::LoadInt { dst, .. }
+            | IL::LoadFloat { dst, .. }
+            | IL::IntOp { dst, .. }
+            | IL::FloatOp { dst, .. }
+            | IL::IntToFloat { dst, .. }
+            | IL::FloatToInt { dst, .. }
+            | IL::IntCall { dst, .. }
+            | IL::FloatCall { dst, .. } => Some(*dst),
+            _ => None,
+        }
+    }
+    /// The register this instruction writes, to replace it.
+    pub fn writes_mut(&mut self) -> Option<&mut Reg> {
+        match self {
+            IL::NewAtom { dst, .. }
+            | IL::NewInt { dst, .. }
+            | IL::NewFloat { dst, .. }
+            | IL::NewMem { dst, .. }
+            | IL::FindAtom { dst, .. }
+            | IL::DerefAtom { dst, .. }
+            | IL::Deref { dst, .. }
+            | IL::AnyMem { dst, .. }
+            | IL::LoadInt { dst, .. }
+            | IL::LoadFloat { dst, .. }
+            | IL::IntOp { dst, .. }
+            | IL::FloatOp { dst, .. }
+            | IL::IntToFloat { dst, .. }
+            | IL::FloatToInt { dst, .. }
+            | IL::IntCall { dst, .. }
+            | IL::FloatCall { dst, .. } => Some(dst),
+            _ => None,
+        }
+    }
+
+    /// The registers this instruction reads.
+    pub fn reads(&self) -> Vec<Reg> {
+        let mut reads = Vec::new();
+        self.visit_reads(&mut |reg| reads.push(reg));
+        reads
+    }
+
+    /// Call `f` with each register this instruction reads.
+    pub(crate) fn visit_reads(&self, f: &mut impl FnMut(Reg)) {
+        match self {
+            IL::NewAtom { mem, .. } | IL::FindAtom { mem, .. } => f(*mem),
+            IL::NewInt { mem, src, .. } | IL::NewFloat { mem, src, .. } => {
+                f(*mem);
+                f(*src);
+            }
+            IL::NewLink {
+                atom1, atom2, mem, ..
+            }
+            | IL::ReLink {
+                atom1, atom2, mem, ..
+            } => {
+                f(*atom1);
+                f(*atom2);
+                f(*mem);
+            }
+            IL::NewMem { parent, .. } | IL::AnyMem { parent, .. } => f(*parent),
+            IL::DerefAtom { src, .. } | IL::Deref { src, .. } => f(*src),
+            IL::RemoveAtom { atom, mem } | IL::RemoveGround { atom, mem } => {
+                f(*atom);
+                f(*mem);
+            }
+            IL::RemoveMem { mem, parent } => {
+                f(*mem);
+                f(*parent);
+            }
+            IL::MoveCells { dst, src } => {
+                f(*dst);
+                f(*src);
+            }
+            IL::SetMemName { mem, .. }
+            | IL::LoadRuleSet { mem, .. }
+            | IL::NAtoms { mem, .. }
+            | IL::NMems { mem, .. }
+            | IL::NoRules { mem }
+            | IL::MemName { mem, .. }
+            | IL::FreeMem { mem } => f(*mem),
+            IL::FreeAtom { atom }
+            | IL::AlterFunctor { atom, .. }
+            | IL::Func { atom, .. }
+            | IL::IsInt { atom }
+            | IL::IsFloat { atom }
+            | IL::IsString { atom }
+            | IL::IsUnary { atom }
+            | IL::IsGround { atom } => f(*atom),
+            IL::EqAtom { lhs, rhs }
+            | IL::NeqAtom { lhs, rhs }
+            | IL::EqGround { lhs, rhs }
+            | IL::NeqGround { lhs, rhs }
+            | IL::IntOp { lhs, rhs, .. }
+            | IL::FloatOp { lhs, rhs, .. }
+            | IL::IntCmp { lhs, rhs, .. }
+            | IL::FloatCmp { lhs, rhs, .. } => {
+                f(*lhs);
+                f(*rhs);
+            }
+            IL::Not(il) => il.visit_reads(f),
+            IL::HistoryCheck { keys, .. } | IL::HistoryAdd { keys, .. } => {
+                for key in keys {
+                    match key {
+                        HistoryKey::Atom(reg) | HistoryKey::Value(reg) => f(*reg),
+                    }
+                }
+            }
+            IL::IntToFloat { src, .. } | IL::FloatToInt { src, .. } => f(*src),
+            IL::IntCall { args, .. } | IL::FloatCall { args, .. } => {
+                for arg in args {
+                    f(*arg);
+                }
+            }
+            IL::LoadInt { .. }
+            | IL::LoadFloat { .. }
+            | IL::Branch { .. }
+            | IL::Jump { .. }
+            | IL::Fail
+            | IL::Commit
+            | IL::Proceed
+            | IL::Label(_) => {}
+        }
+    }
+
+    /// Replace each register this instruction reads or writes with `f` of it.
+    pub fn map_regs(&mut self, f: &mut impl FnMut(Reg) -> Reg) {
+        let mut map = |reg: &mut Reg| *reg = f(*reg);
+        match self {
+            IL::NewAtom { dst, mem, .. } | IL::FindAtom { dst, mem, .. } => {
+                map(dst);
+                map(mem);
+            }
+            IL::NewInt { dst, mem, src } | IL::NewFloat { dst, mem, src } => {
+                map(dst);
+                map(mem);
+                map(src);
+            }
+            IL::NewLink {
+                atom1, atom2, mem, ..
+            }
+            | IL::ReLink {
+                atom1, atom2, mem, ..
+            } => {
+                map(atom1);
+                map(atom2);
+                map(mem);
+            }
+            IL::NewMem { dst, parent } | IL::AnyMem { dst, parent, .. } => {
+                map(dst);
+                map(parent);
+            }
+            IL::DerefAtom { dst, src, .. }
+            | IL::Deref { dst, src, .. }
+            | IL::IntToFloat { dst, src }
+            | IL::FloatToInt { dst, src } => {
+                map(dst);
+                map(src);
+            }
+            IL::RemoveAtom { atom: a, mem: b }
+            | IL::RemoveGround { atom: a, mem: b }
+            | IL::RemoveMem { mem: a, parent: b }
+            | IL::MoveCells { dst: a, src: b }
+            | IL::EqAtom { lhs: a, rhs: b }
+            | IL::NeqAtom { lhs: a, rhs: b }
+            | IL::EqGround { lhs: a, rhs: b }
+            | IL::NeqGround { lhs: a, rhs: b }
+            | IL::IntCmp { lhs: a, rhs: b, .. }
+            | IL::FloatCmp { lhs: a, rhs: b, .. } => {
+                map(a);
+                map(b);
+            }
+            IL::SetMemName { mem: reg, .. }
+            | IL::LoadRuleSet { mem: reg, .. }
+            | IL::NAtoms { mem: reg, .. }
+            | IL::NMems { mem: reg, .. }
+            | IL::NoRules { mem: reg }
+            | IL::MemName { mem: reg, .. }
+            | IL::FreeMem { mem: reg }
+            | IL::FreeAtom { atom: reg }
+            | IL::AlterFunctor { atom: reg, .. }
+            | IL::Func { atom: reg, .. }
+            | IL::IsInt { atom: reg }
+            | IL::IsFloat { atom: reg }
+            | IL::IsString { atom: reg }
+            | IL::IsUnary { atom: reg }
+            | IL::IsGround { atom: reg }
+            | IL::LoadInt { dst: reg, .. }
+            | IL::LoadFloat { dst: reg, .. } => map(reg),
+            IL::IntOp { dst, lhs, rhs, .. } | IL::FloatOp { dst, lhs, rhs, .. } => {
+                map(dst);
+                map(lhs);
+                map(rhs);
+            }
+            IL::Not(il) => il.map_regs(f),
+            IL::HistoryCheck { keys, .. } | IL::HistoryAdd { keys, .. } => {
+                for key in keys {
+                    match key {
+                        HistoryKey::Atom(reg) | HistoryKey::Value(reg) => map(reg),
+                    }
+                }
+            }
+            IL::IntCall { dst, args, .. } | IL::FloatCall { dst, args, .. } => {
+                map(dst);
+                args.iter_mut().for_each(map);
+            }
+            IL::Branch { .. }
+            | IL::Jump { .. }
+            | IL::Fail
+            | IL::Commit
+            | IL::Proceed
+            | IL::Label(_) => {}
+        }
+    }
+
+    /// Print this instruction with the functors of `functors`.
+    pub fn display<'a>(&'a self, functors: &'a FunctorTable) -> Resolved<'a> {
+        Resolved { il: self, functors }
+    }
+
+    /// Connect the two ends of `link`, whose atoms are held in the registers `reg` gives for their ids.
+    pub fn new_link(link: &Link, mem: Reg, reg: impl Fn(AtomId) -> Reg) -> Self {
         if let (Some(link1), Some(link2)) = (link.link1, link.link2) {
-            IL::NewLink(link1.0.into(), link1.1, link2.0.into(), link2.1, mem_id)
+            IL::NewLink {
+                atom1: reg(link1.0.into()),
+                port1: Port(link1.1),
+                atom2: reg(link2.0.into()),
+                port2: Port(link2.1),
+                mem,
+            }
         } else {
             panic!("Link is not fully defined")
         }
     }
 }
+
+/// An instruction and where the source it is generated from is written, if anywhere.
+#[derive(Debug, Clone)]
+pub struct Instr {
+    pub il: IL,
+    pub span: Option<Span>,
+}
+
+impl Instr {
+    pub fn new(il: IL, span: Span) -> Self {
+        Self {
+            il,
+            span: Some(span),
+        }
+    }
+}
+
+impl From<IL> for Instr {
+    fn from(il: IL) -> Self {
+        Self { il, span: None }
+    }
+}
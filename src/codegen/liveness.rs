@@ -0,0 +1,171 @@
+//! Which registers of a rule hold a value that may still be read.
+//!
+//! Besides the jumps between blocks, a rule goes back to its last choice when a check fails:
+//! to the last `find_atom` or `any_mem` to try its next candidate, or to the instruction after the
+//! last `branch`. The registers read from there on are live until the rule commits.
+
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use super::il::{Instr, Reg, IL};
+
+/// An instruction of a rule, as its block and its index in the block.
+pub type Point = (usize, usize);
+
+/// The registers live at the instructions a rule jumps or goes back to.
+///
+/// Only these are kept, what is live at the other instructions is found by scanning their block.
+#[derive(Debug)]
+pub struct Liveness {
+    /// Block -> instruction -> the instructions it goes on with other than the one after it
+    jumps: Vec<BTreeMap<usize, Vec<Point>>>,
+    /// Block -> instruction jumped or gone back to -> the registers live before it
+    live_in: Vec<BTreeMap<usize, BTreeSet<Reg>>>,
+}
+
+impl Liveness {
+    /// Analyze the blocks of a rule, starting from the first one.
+    pub fn analyze(blocks: &[Vec<Instr>]) -> Self {
+        let mut liveness = Liveness {
+            jumps: vec![BTreeMap::new(); blocks.len()],
+            live_in: vec![BTreeMap::new(); blocks.len()],
+        };
+        if !blocks.is_empty() {
+            let mut visited = HashSet::new();
+            liveness.flow(blocks, 0, None, &mut visited);
+        }
+        let targets: Vec<Point> = liveness
+            .jumps
+            .iter()
+            .flat_map(|jumps| jumps.values().flatten())
+            .copied()
+            .collect();
+        for (block, index) in targets {
+            liveness.live_in[block].insert(index, BTreeSet::new());
+        }
+
+        // block -> the blocks that jump or go back into it, to scan again when it changes
+        let mut sources = vec![Vec::new(); blocks.len()];
+        for (block, jumps) in liveness.jumps.iter().enumerate() {
+            for (target, _) in jumps.values().flatten() {
+                sources[*target].push(block);
+            }
+        }
+        let mut dirty = vec![true; blocks.len()];
+        while dirty.contains(&true) {
+            for block in (0..blocks.len()).rev() {
+                if !std::mem::take(&mut dirty[block]) {
+                    continue;
+                }
+                let mut updates = Vec::new();
+                liveness.scan(blocks, block, |index, live_out, instr| {
+                    if let Some(known) = liveness.live_in[block].get(&index) {
+                        let live_in = live_in(live_out, instr);
+                        if *known != live_in {
+                            updates.push((index, live_in));
+                        }
+                    }
+                });
+                if !updates.is_empty() {
+                    for source in &sources[block] {
+                        dirty[*source] = true;
+                    }
+                }
+                liveness.live_in[block].extend(updates);
+            }
+        }
+        liveness
+    }
+
+    /// Call `f` with each instruction of `block` and the registers live after it, from the last
+    /// instruction to the first.
+    pub fn scan(
+        &self,
+        blocks: &[Vec<Instr>],
+        block: usize,
+        mut f: impl FnMut(usize, &BTreeSet<Reg>, &Instr),
+    ) {
+        let mut live = BTreeSet::new();
+        for (index, instr) in blocks[block].iter().enumerate().rev() {
+            if !falls_through(&instr.il) {
+                live.clear();
+            }
+            for (target, at) in self.jumps[block].get(&index).into_iter().flatten() {
+                live.extend(&self.live_in[*target][at]);
+            }
+            f(index, &live, instr);
+            if let Some(reg) = instr.il.writes() {
+                live.remove(&reg);
+            }
+            instr.il.visit_reads(&mut |reg| {
+                live.insert(reg);
+            });
+        }
+    }
+
+    /// The registers that may be read after `point`.
+    pub fn live_out(&self, blocks: &[Vec<Instr>], (block, index): Point) -> BTreeSet<Reg> {
+        let mut result = BTreeSet::new();
+        self.scan(blocks, block, |i, live_out, _| {
+            if i == index {
+                result = live_out.clone();
+            }
+        });
+        result
+    }
+
+    /// Record where each instruction of `block` goes on with besides the next one,
+    /// `retry` being where the rule goes back to when one of them fails.
+    fn flow(
+        &mut self,
+        blocks: &[Vec<Instr>],
+        block: usize,
+        mut retry: Option<Point>,
+        visited: &mut HashSet<(usize, Option<Point>)>,
+    ) {
+        if !visited.insert((block, retry)) {
+            return;
+        }
+        for (i, instr) in blocks[block].iter().enumerate() {
+            let mut jumps = Vec::new();
+            if instr.il.can_fail() {
+                jumps.extend(retry);
+            }
+            match &instr.il {
+                IL::Branch { target } => {
+                    jumps.push((target.0, 0));
+                    self.flow(blocks, target.0, Some((block, i + 1)), visited);
+                }
+                IL::Jump { target } => {
+                    jumps.push((target.0, 0));
+                    self.flow(blocks, target.0, retry, visited);
+                }
+                IL::Commit => retry = None,
+                il if il.is_choice() => retry = Some((block, i)),
+                _ => {}
+            }
+            if !jumps.is_empty() {
+                self.jumps[block].entry(i).or_default().extend(jumps);
+            }
+            if instr.il.is_terminator() {
+                return;
+            }
+        }
+    }
+}
+
+/// Whether the instruction after `il` runs when it succeeds.
+/// A branch goes on with the next instruction only when its block fails.
+fn falls_through(il: &IL) -> bool {
+    !il.is_terminator() && !matches!(il, IL::Branch { .. })
+}
+
+fn live_in(live_out: &BTreeSet<Reg>, instr: &Instr) -> BTreeSet<Reg> {
+    let mut live = live_out.clone();
+    if let Some(reg) = instr.il.writes() {
+        live.remove(&reg);
+    }
+    instr.il.visit_reads(&mut |reg| {
+        live.insert(reg);
+    });
+    live
+}
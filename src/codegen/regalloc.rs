@@ -0,0 +1,70 @@
+//! Register allocation of a rule: registers that are never live at the same time share one.
+
+use super::{
+    il::{Instr, Reg},
+    liveness::Liveness,
+};
+
+/// Renumber the registers of a rule from 1, reusing the registers whose values are dead.
+/// Register 0 keeps holding the membrane the rule is applied in.
+///
+/// Returns the number of registers the rule uses.
+pub(crate) fn allocate(blocks: &mut [Vec<Instr>]) -> usize {
+    let liveness = Liveness::analyze(blocks);
+    let mut count = 1;
+    for instr in blocks.iter().flatten() {
+        instr
+            .il
+            .visit_reads(&mut |reg| count = count.max(reg.0 + 1));
+        count = count.max(instr.il.writes().map_or(0, |reg| reg.0 + 1));
+    }
+
+    // register -> the registers live when it is written, or that are live when it is written
+    let mut interference: Vec<Vec<usize>> = vec![Vec::new(); count];
+    for block in 0..blocks.len() {
+        liveness.scan(blocks, block, |_, live_out, instr| {
+            let Some(written) = instr.il.writes() else {
+                return;
+            };
+            for live in live_out {
+                if *live != written {
+                    interference[written.0].push(live.0);
+                    interference[live.0].push(written.0);
+                }
+            }
+        });
+    }
+
+    // registers in the order they first appear
+    let mut colors: Vec<Option<usize>> = vec![None; count];
+    colors[0] = Some(0);
+    let mut taken = Vec::new();
+    let mut color = |reg: Reg| {
+        if colors[reg.0].is_some() {
+            return;
+        }
+        taken.clear();
+        taken.extend(
+            interference[reg.0]
+                .iter()
+                .filter_map(|other| colors[*other]),
+        );
+        colors[reg.0] = (1..).find(|c| !taken.contains(c));
+    };
+    for instr in blocks.iter().flatten() {
+        instr.il.visit_reads(&mut color);
+        if let Some(reg) = instr.il.writes() {
+            color(reg);
+        }
+    }
+
+    for instr in blocks.iter_mut().flatten() {
+        instr.il.map_regs(&mut |reg| Reg(colors[reg.0].unwrap()));
+    }
+    colors
+        .iter()
+        .flatten()
+        .map(|color| color + 1)
+        .max()
+        .unwrap_or(1)
+}
@@ -1,129 +1,235 @@
-use std::fmt::Display;
+use std::collections::{HashMap, HashSet};
 
 use colored::Colorize;
 
-use crate::parser::{
-    data::{self, Membrane, MembraneId, Symbol},
-    rule_parser::{self, Case},
+use crate::{
+    diagnostic::Span,
+    functor::FunctorTable,
+    parser::{
+        data::{self, Atom, Link, Membrane, MembraneId, Symbol},
+        rule_parser::{self, Case, GuardNode, Type},
+    },
+    source::SourceMap,
 };
 
-use super::{il::IL, ILGenerator};
+use super::{
+    guard_gen::Clause,
+    il::{BlockId, Instr, MemKind, Port, Reg, RuleSetId, IL},
+    regalloc, write_block, ILGenerator,
+};
 
+/// A table of the combinations of atoms a rule has already been applied to, used by `uniq`.
 #[derive(Debug, Default)]
-pub struct CaseIL {
-    pub guard: Vec<IL>,
-    pub body: Vec<IL>,
+pub struct HistoryTable {
+    /// Names of the links and process contexts the entries are made of.
+    pub keys: Vec<String>,
 }
 
 #[derive(Debug, Default)]
 pub struct RuleIL {
     pub name: String,
-    pub pattern: Vec<IL>,
-    pub removal: Vec<IL>,
-    pub cases: Vec<CaseIL>,
+    /// Where the rule is written.
+    pub span: Span,
+    pub priority: i64,
+    pub once: bool,
+    pub disabled: bool,
+    pub histories: Vec<HistoryTable>,
+    /// Number of registers the rule uses, so a runtime can allocate them all at once.
+    pub registers: usize,
+    /// Basic blocks of the rule, referred to by their index, starting from the first.
+    ///
+    /// The first block matches the head and branches to a block for each case,
+    /// which checks its guard, commits, removes the head and builds the body.
+    pub blocks: Vec<Vec<Instr>>,
 }
 
-impl Display for RuleIL {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "{} {}", "Rule".magenta(), self.name)?;
-        writeln!(f, "{}", "Pattern".green())?;
-        for il in &self.pattern {
-            writeln!(f, "{}", il)?;
-        }
-        writeln!(f, "{}", "Cases".bright_blue())?;
-        let mut i = 0;
-        for case in &self.cases {
-            writeln!(f, "{} {}", "Case".blue(), i)?;
-            if !case.guard.is_empty() {
-                writeln!(f, "{}", "Guard".yellow())?;
-                for il in &case.guard {
-                    writeln!(f, "{}", il)?;
-                }
-            }
-            writeln!(f, "{}", "Body".bright_green())?;
-            for il in &case.body {
-                writeln!(f, "{}", il)?;
+impl RuleIL {
+    /// Renumber the registers of this rule so the values never live at the same time share one.
+    pub fn allocate_registers(&mut self) {
+        self.registers = regalloc::allocate(&mut self.blocks);
+    }
+
+    /// Write this rule as text, resolving its functors in `functors`.
+    pub(super) fn write(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        functors: &FunctorTable,
+        sources: Option<&SourceMap>,
+    ) -> std::fmt::Result {
+        write!(f, "{} {}", "Rule".magenta(), self.name)?;
+        if self.priority != 0 {
+            write!(f, " @priority({})", self.priority)?;
+        }
+        if self.once {
+            write!(f, " @once")?;
+        }
+        if self.disabled {
+            write!(f, " @disabled")?;
+        }
+        writeln!(f)?;
+        self.write_blocks(f, functors, sources)
+    }
+
+    /// Write the registers, history tables and blocks of this rule, without its name.
+    pub(super) fn write_blocks(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        functors: &FunctorTable,
+        sources: Option<&SourceMap>,
+    ) -> std::fmt::Result {
+        writeln!(f, "{} {}", "Registers".cyan(), self.registers)?;
+        if !self.histories.is_empty() {
+            writeln!(f, "{}", "History".cyan())?;
+            for (i, table) in self.histories.iter().enumerate() {
+                writeln!(f, "table   \t{}, {}", i, table.keys.join(", "))?;
             }
-            i += 1;
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            writeln!(f, "{} {}", "Block".blue(), i)?;
+            write_block(f, block, functors, sources)?;
         }
         Ok(())
     }
 }
 
 impl ILGenerator {
-    pub(crate) fn emit_rule(&mut self, mem_id: MembraneId, rule: RuleIL) {
-        self.rule_sets
-            .entry(mem_id)
-            .or_insert_with(Vec::new)
-            .push(rule);
+    pub(crate) fn emit_rule(&mut self, rule_set: RuleSetId, rule: RuleIL) {
+        let rules = self.rule_sets.entry(rule_set).or_default();
+        rules.push(rule);
+        // rules of the same priority keep their order in the source
+        rules.sort_by_key(|rule| std::cmp::Reverse(rule.priority));
     }
 }
 
 #[derive(Debug)]
 pub(crate) struct RuleGenerator<'a> {
-    rule: &'a rule_parser::Rule,
-    register: usize,
-    remove_stack: Vec<(Symbol, usize)>,
+    pub(super) rule: &'a rule_parser::Rule,
+    /// The next free register, register 0 holds the membrane the rule is applied in.
+    pub(super) register: usize,
+    /// Removal of the matched atoms and membranes, in the order they are matched,
+    /// with where they are written
+    remove_stack: Vec<(IL, Span)>,
+    /// Head atom id -> register holding the matched atom
+    pub(super) atom_regs: HashMap<usize, Reg>,
+    /// Head atom id -> register holding the membrane it is found in
+    atom_mems: HashMap<usize, Reg>,
+    /// Head membrane id -> register holding the matched membrane
+    pub(super) mem_regs: HashMap<usize, Reg>,
+    /// Smallest atom or membrane id of the body of the case being generated, and the register
+    /// holding it. The ids of a body are consecutive, so the atoms and membranes of the body and
+    /// their registers are indexed by their ids from this one.
+    body_first: (usize, Reg),
+    /// Atoms and membranes of the body of the case being generated, by their ids from the first
+    body_atoms: Vec<Option<&'a Atom>>,
+    body_mems: Vec<Option<&'a Membrane>>,
+    /// Temporary variable of the clause being generated -> register holding its value, and its type
+    temp_vars: HashMap<&'a str, (Reg, Type)>,
+    /// Membranes of the bodies whose rules are loaded, with the ids of their rule sets,
+    /// which are yet to be generated
+    pub(crate) rule_sets: Vec<(RuleSetId, &'a Membrane)>,
+    /// Id of the first rule set this rule loads.
+    first_rule_set: usize,
     pub(crate) il: RuleIL,
 }
 
 impl<'a> RuleGenerator<'a> {
-    pub fn new(rule: &'a rule_parser::Rule) -> Self {
+    pub fn new(rule: &'a rule_parser::Rule, first_rule_set: usize) -> Self {
         Self {
             rule,
-            register: 0,
+            register: 1,
             remove_stack: Vec::new(),
+            atom_regs: HashMap::new(),
+            atom_mems: HashMap::new(),
+            mem_regs: HashMap::new(),
+            body_first: (0, Reg(0)),
+            body_atoms: Vec::new(),
+            body_mems: Vec::new(),
+            temp_vars: HashMap::new(),
+            rule_sets: Vec::new(),
+            first_rule_set,
             il: RuleIL::default(),
         }
     }
 
     pub(crate) fn gen(&mut self) {
         self.il.name = self.rule.name.clone();
+        self.il.span = self.rule.span;
+        self.il.priority = self.rule.priority;
+        self.il.once = self.rule.once;
+        self.il.disabled = self.rule.disabled;
+        self.il.blocks.push(Vec::new());
         self.gen_pattern();
-        self.gen_guard();
         self.gen_cases();
+        self.il.allocate_registers();
+    }
+
+    pub(super) fn new_register(&mut self) -> Reg {
+        self.register += 1;
+        Reg(self.register - 1)
+    }
+
+    /// The register holding a membrane of the rule: the membrane the rule is applied in,
+    /// a matched membrane, or a membrane created by the body.
+    fn mem_reg(&self, mem: MembraneId) -> Reg {
+        if mem == self.rule.membrane {
+            return Reg(0);
+        }
+        match self.mem_regs.get(&mem) {
+            Some(reg) => *reg,
+            None => self.body_reg(mem),
+        }
+    }
+
+    /// The register holding an atom or membrane created by the body.
+    fn body_reg(&self, id: usize) -> Reg {
+        let (first, reg) = self.body_first;
+        Reg(reg.0 + id - first)
     }
 
     fn gen_pattern(&mut self) {
-        let mem = &self.rule.pattern;
-        let mut atom_counter = 0;
-        let mut mem_counter = 0;
-        for p in &mem.process {
-            let reg = self.register;
-            let rule = self.rule;
+        let rule = self.rule;
+        self.gen_pattern_process(&rule.pattern.process, Reg(0));
+    }
+
+    /// Find the atoms and membranes of the head that are directly in the membrane in `parent`.
+    fn gen_pattern_process(&mut self, process: &[Symbol], parent: Reg) {
+        let rule = self.rule;
+        for p in process {
             match p {
-                data::Symbol::Atom(_) => {
-                    let atom = rule.atoms.get(atom_counter).unwrap();
-                    atom_counter += 1;
-                    self.il.pattern.push(IL::FindAtom(
-                        reg,
-                        atom.membrane,
-                        atom.name.clone(),
-                        atom.links.len(),
-                    ));
-                    self.remove_stack.push((Symbol::Atom(reg), atom.membrane));
-                    self.register += 1;
-                    // todo: gen process
-                }
-                data::Symbol::Membrane(_) => {
-                    let mem = rule.mems.get(mem_counter).unwrap();
-                    mem_counter += 1;
-                    self.il.pattern.push(IL::AnyMem(
-                        reg,
-                        rule.membrane,
-                        0,
-                        if !mem.name.is_empty() {
+                data::Symbol::Atom(id) => self.find_atom(*id, parent, None),
+                data::Symbol::Membrane(id) => {
+                    let mem = rule.mems.iter().find(|m| m.id == *id).unwrap();
+                    let reg = self.new_register();
+                    self.mem_regs.insert(*id, reg);
+                    let any_mem = IL::AnyMem {
+                        dst: reg,
+                        parent,
+                        kind: MemKind::Normal,
+                        name: if !mem.name.is_empty() {
                             Some(mem.name.clone())
                         } else {
                             None
                         },
-                    ));
-                    self.remove_stack
-                        .push((Symbol::Membrane(reg), mem.membrane));
-                    self.register += 1;
+                    };
+                    self.il.blocks[0].push(Instr::new(any_mem, mem.span));
+                    let remove = IL::RemoveMem { mem: reg, parent };
+                    self.remove_stack.push((remove, mem.span));
 
-                    self.il.pattern.push(IL::NAtoms(reg, mem.process.len()))
+                    // a process context matches any number of atoms
+                    if !mem
+                        .process
+                        .iter()
+                        .any(|s| matches!(s, Symbol::ProcContext(_)))
+                    {
+                        let natoms = IL::NAtoms {
+                            mem: reg,
+                            count: rule.atoms.iter().filter(|a| a.membrane == *id).count(),
+                        };
+                        self.il.blocks[0].push(Instr::new(natoms, mem.span))
+                    }
+                    self.gen_pattern_process(&mem.process, reg);
                 }
+                data::Symbol::ProcContext(_) => {}
                 _ => {
                     unreachable!("Unexpected symbol: {:?}", p);
                 }
@@ -131,88 +237,272 @@ impl<'a> RuleGenerator<'a> {
         }
     }
 
-    fn gen_guard(&mut self) {}
+    /// Find an atom of the head in the membrane in `mem`, then the atoms written as its arguments,
+    /// checking the links of each to the atoms found before it.
+    ///
+    /// An atom written as an argument is linked by its last port to `parent`, the atom it is
+    /// written in and the port it is written at.
+    fn find_atom(&mut self, id: usize, mem: Reg, parent: Option<(Reg, usize)>) {
+        let rule = self.rule;
+        let atom = rule.atoms.iter().find(|a| a.id == id).unwrap();
+        let reg = self.new_register();
+        let find = IL::FindAtom {
+            dst: reg,
+            mem,
+            functor: atom.functor,
+        };
+        self.il.blocks[0].push(Instr::new(find, atom.span));
+        // two atoms of the head are never the same atom
+        for other in &rule.atoms {
+            if other.functor != atom.functor || other.membrane != atom.membrane {
+                continue;
+            }
+            if let Some(other) = self.atom_regs.get(&other.id) {
+                let neq = IL::NeqAtom {
+                    lhs: *other,
+                    rhs: reg,
+                };
+                self.il.blocks[0].push(Instr::new(neq, atom.span));
+            }
+        }
+        self.atom_regs.insert(id, reg);
+        self.atom_mems.insert(id, mem);
+        if let Some((parent, port)) = parent {
+            self.check_link((parent, port), (reg, atom.links.len()), atom.span);
+        }
+        for (port, link) in atom.links.iter().enumerate() {
+            let Symbol::Link(link) = link else {
+                continue;
+            };
+            let link = &rule.links[link];
+            let here = (Symbol::Atom(id), port);
+            let (other, span) = if link.link1 == Some(here) {
+                (link.link2, link.span1)
+            } else {
+                (link.link1, link.span2)
+            };
+            let Some((Symbol::Atom(other), other_port)) = other else {
+                continue;
+            };
+            // a link between two ports of this atom is checked from the second one
+            if other == id && other_port > port {
+                continue;
+            }
+            if let Some(other) = self.atom_regs.get(&other) {
+                self.check_link((*other, other_port), (reg, port), span.unwrap_or(atom.span));
+            }
+        }
+        let remove = IL::RemoveAtom { atom: reg, mem };
+        self.remove_stack.push((remove, atom.span));
+        for (port, arg) in atom.links.iter().enumerate() {
+            if let Symbol::Atom(arg) = arg {
+                self.find_atom(*arg, mem, Some((reg, port)));
+            }
+        }
+    }
+
+    /// Check that a port of a found atom is linked to a port of another, both given as the
+    /// register holding the atom and the port.
+    fn check_link(
+        &mut self,
+        (src, src_port): (Reg, usize),
+        (dst, dst_port): (Reg, usize),
+        span: Span,
+    ) {
+        let reached = self.new_register();
+        let deref = IL::Deref {
+            dst: reached,
+            src,
+            src_port: Port(src_port),
+            dst_port: Port(dst_port),
+        };
+        self.il.blocks[0].push(Instr::new(deref, span));
+        let eq = IL::EqAtom {
+            lhs: reached,
+            rhs: dst,
+        };
+        self.il.blocks[0].push(Instr::new(eq, span));
+    }
 
+    /// Generate a block for each case, tried in order after the head is matched.
+    /// The rule fails back to the head once no case applies.
     fn gen_cases(&mut self) {
-        for (symbol, mem) in self.remove_stack.iter().rev() {
-            match symbol {
-                Symbol::Atom(id) => {
-                    self.il.removal.push(IL::RemoveAtom(*id, *mem));
-                }
-                Symbol::Membrane(id) => {
-                    self.il.removal.push(IL::RemoveMem(*id, *mem));
-                }
-                _ => {
-                    unreachable!()
-                }
+        let removal: Vec<Instr> = self
+            .remove_stack
+            .iter()
+            .rev()
+            .map(|(remove, span)| Instr::new(remove.clone(), *span))
+            .collect();
+        let rule = self.rule;
+        for case in &rule.cases {
+            // a guard with `||` becomes one case for each of its clauses
+            let clauses = match &case.constraint {
+                Some(guard) => guard.clauses(),
+                None => vec![vec![]],
+            };
+            for (i, clause) in clauses.iter().enumerate() {
+                let target = BlockId(self.il.blocks.len());
+                self.il.blocks[0].push(IL::Branch { target }.into());
+                let block = self.gen_case(case, i, clause, &removal);
+                self.il.blocks.push(block);
             }
         }
-        for case in &self.rule.cases {
-            let il = self.gen_case(case);
-            self.il.cases.push(il);
-        }
+        self.il.blocks[0].push(IL::Fail.into());
     }
 
-    fn gen_case(&mut self, case: &Case) -> CaseIL {
-        let mut il = CaseIL::default();
+    fn gen_case(
+        &mut self,
+        case: &'a Case,
+        clause_id: usize,
+        clause: &[GuardNode],
+        removal: &[Instr],
+    ) -> Vec<Instr> {
+        let guard = self.gen_guard(case, clause_id, clause);
+        // what is checked and committed comes from the guard
+        let from_guard = |il| Instr {
+            il,
+            span: case.guard_span,
+        };
+        let mut block: Vec<Instr> = guard.il.iter().cloned().map(from_guard).collect();
+        block.push(Instr::new(IL::Commit, case.span));
+        block.extend(guard.commit.iter().cloned().map(from_guard));
+        // the data atoms first, their membranes may be removed with the head
+        block.extend(self.remove_data(case, &guard));
+        block.extend_from_slice(removal);
+        self.temp_vars = case
+            .vars
+            .iter()
+            .zip(&guard.vars)
+            .zip(&guard.types.vars)
+            .map(|((var, reg), ty)| {
+                let reg = reg.expect("every temporary variable is computed");
+                (var.name.as_str(), (reg, ty.unwrap_or(Type::Int)))
+            })
+            .collect();
+
+        let rule = self.rule;
+        let (atoms, mems) = (&rule.case_atoms[case.id], &rule.case_mems[case.id]);
+        let ids = atoms.iter().map(|atom| atom.id);
+        let ids = ids.chain(mems.iter().map(|mem| mem.id));
+        let (first, last) = (ids.clone().min().unwrap_or(0), ids.max().unwrap_or(0));
+        self.body_first = (first, Reg(self.register));
+        self.register += last - first + 1;
+        self.body_atoms = vec![None; last - first + 1];
+        for atom in atoms {
+            self.body_atoms[atom.id - first] = Some(atom);
+        }
+        self.body_mems = vec![None; last - first + 1];
+        for mem in mems {
+            self.body_mems[mem.id - first] = Some(mem);
+        }
+        // rules written in the body are added to the membrane the rule is applied in
+        block.extend(self.load_rule_set(&case.body, Reg(0)));
         for process in &case.body.process {
-            let mut unit = self.gen_unit(*process, Some(case.id));
-            il.body.append(&mut unit);
+            let mut unit = self.gen_unit(*process, Reg(0), case.id);
+            block.append(&mut unit);
+        }
+        block.push(Instr::new(IL::Proceed, case.span));
+        block
+    }
+
+    /// Remove the data atoms of the links the guard of `case` types that its body does not use,
+    /// which nothing would be connected to once the head is removed.
+    fn remove_data(&self, case: &Case, guard: &Clause) -> Vec<Instr> {
+        let used: HashSet<&str> = self.rule.case_links[case.id]
+            .values()
+            .map(|link| link.name.as_str())
+            .collect();
+        let mut il = vec![];
+        for (id, link) in &self.rule.links {
+            if link.link2.is_some() || used.contains(link.name.as_str()) {
+                continue;
+            }
+            let symbol = Symbol::Link(*id);
+            let (Some(ty), Some(data)) =
+                (guard.types.symbols.get(&symbol), guard.symbols.get(&symbol))
+            else {
+                continue;
+            };
+            let Some((Symbol::Atom(owner), _)) = link.link1 else {
+                continue;
+            };
+            // the data atom is in the membrane of the head atom it is connected to
+            let (mem, span) = (self.atom_mems[&owner], link.span1.unwrap_or(case.span));
+            let remove = match ty {
+                Type::Ground => IL::RemoveGround { atom: *data, mem },
+                _ => IL::RemoveAtom { atom: *data, mem },
+            };
+            il.push(Instr::new(remove, span));
         }
         il
     }
 
-    fn gen_unit(&mut self, symbol: Symbol, case: Option<usize>) -> Vec<IL> {
+    /// Generate a process of a body written directly in the membrane in `mem`.
+    fn gen_unit(&mut self, symbol: Symbol, mem: Reg, case: usize) -> Vec<Instr> {
         match symbol {
             Symbol::Atom(id) => {
-                if let Some(case_id) = case {
-                    for atom in &self.rule.case_atoms[case_id] {
-                        if atom.id == id {
-                            return self.gen_atom(atom, case);
-                        }
-                    }
-                } else {
-                    for atom in &self.rule.atoms {
-                        if atom.id == id {
-                            return self.gen_atom(atom, case);
-                        }
-                    }
-                }
-                unreachable!()
+                let atom = self.body_atoms[id - self.body_first.0].unwrap();
+                self.gen_atom(atom, case)
             }
             Symbol::Membrane(id) => {
-                for mem in &self.rule.mems {
-                    if mem.id == id {
-                        return self.gen_mem(mem, case);
-                    }
-                }
-                unreachable!()
+                let mem = self.body_mems[id - self.body_first.0].unwrap();
+                self.gen_mem(mem, case)
             }
+            Symbol::ProcContext(id) => self.move_cells(id, mem).into_iter().collect(),
             _ => {
                 unreachable!()
             }
         }
     }
 
-    fn gen_atom(&mut self, atom: &crate::parser::data::Atom, case: Option<usize>) -> Vec<IL> {
-        let mut il = vec![IL::NewAtom(
-            atom.id,
-            atom.membrane,
-            atom.name.clone(),
-            atom.links.len(),
-        )];
+    /// Create an atom of a body and the atoms written as its arguments, then connect its links
+    /// whose other end is already created.
+    fn gen_atom(&mut self, atom: &Atom, case: usize) -> Vec<Instr> {
+        let (dst, mem) = (self.body_reg(atom.id), self.mem_reg(atom.membrane));
+        let new_atom = IL::NewAtom {
+            dst,
+            mem,
+            functor: atom.functor,
+        };
+        let mut il = vec![Instr::new(new_atom, atom.span)];
 
-        for link in &atom.links {
-            if let Symbol::Link(id) = link {
-                let link = if let Some(case_id) = case {
-                    self.rule.case_links[case_id].get(id).unwrap()
-                } else {
-                    self.rule.links.get(id).unwrap()
+        // an atom written as an argument is connected by its last port, and is created before
+        // the links of its parent, which may lead to it
+        for (port, arg) in atom.links.iter().enumerate() {
+            if let Symbol::Atom(arg) = arg {
+                let arg = self.body_atoms[arg - self.body_first.0].unwrap();
+                il.extend(self.gen_atom(arg, case));
+                let link = IL::NewLink {
+                    atom1: dst,
+                    port1: Port(port),
+                    atom2: self.body_reg(arg.id),
+                    port2: Port(arg.links.len()),
+                    mem,
                 };
-                if let Some(link2) = link.link2 {
-                    if Into::<usize>::into(link2.0) == atom.id {
-                        il.push(IL::new_link(link, 0));
+                il.push(Instr::new(link, arg.span));
+            }
+        }
+
+        for (port, link) in atom.links.iter().enumerate() {
+            if let Symbol::Link(id) = link {
+                let link = &self.rule.case_links[case][id];
+                match link.link2 {
+                    Some(link2) => {
+                        // linked once its second end is reached, which is on this atom again
+                        // for a link between two of its ports
+                        if Into::<usize>::into(link2.0) == atom.id && link2.1 == port {
+                            let span = link.span2.unwrap_or(atom.span);
+                            let mem = self.mem_reg(atom.membrane);
+                            let link = IL::new_link(link, mem, |id| self.body_reg(id));
+                            il.push(Instr::new(link, span));
+                        }
                     }
+                    // the only end of the link in the body takes the value of a temporary
+                    // variable, or continues a link of the head
+                    None => match self.temp_vars.get(link.name.as_str()) {
+                        Some(&(value, ty)) => il.extend(self.new_data(atom, link, value, ty)),
+                        None => il.extend(self.relink(atom, link)),
+                    },
                 }
             }
         }
@@ -220,21 +510,93 @@ impl<'a> RuleGenerator<'a> {
         il
     }
 
-    fn gen_mem(&mut self, mem: &Membrane, case: Option<usize>) -> Vec<IL> {
+    /// Connect the end of `link` on a body atom to what the link of the head with the same name
+    /// is connected to.
+    fn relink(&self, atom: &Atom, link: &Link) -> Option<Instr> {
+        let (_, port) = link.link1?;
+        let (id, _) = self
+            .rule
+            .links
+            .iter()
+            .find(|(_, head)| head.name == link.name && head.link2.is_none())?;
+        let (src, src_port) = self.head_endpoint(Symbol::Link(*id))?;
+        let relink = IL::ReLink {
+            atom1: self.body_reg(atom.id),
+            port1: Port(port),
+            atom2: src,
+            port2: src_port,
+            mem: self.mem_reg(atom.membrane),
+        };
+        Some(Instr::new(relink, link.span1.unwrap_or(atom.span)))
+    }
+
+    /// Create a data atom holding the value in `src` and connect it to the end of `link` on a
+    /// body atom.
+    fn new_data(&mut self, atom: &Atom, link: &Link, src: Reg, ty: Type) -> Vec<Instr> {
+        let Some((_, port)) = link.link1 else {
+            return vec![];
+        };
+        let (dst, mem) = (self.new_register(), self.mem_reg(atom.membrane));
+        let new = match ty {
+            Type::Float => IL::NewFloat { dst, mem, src },
+            _ => IL::NewInt { dst, mem, src },
+        };
+        let connect = IL::NewLink {
+            atom1: self.body_reg(atom.id),
+            port1: Port(port),
+            atom2: dst,
+            port2: Port(0),
+            mem,
+        };
+        let span = link.span1.unwrap_or(atom.span);
+        vec![Instr::new(new, span), Instr::new(connect, span)]
+    }
+
+    fn gen_mem(&mut self, mem: &'a Membrane, case: usize) -> Vec<Instr> {
         let mut il = Vec::new();
-        il.push(IL::NewMem(mem.id, mem.membrane));
+        let dst = self.body_reg(mem.id);
+        let new_mem = IL::NewMem {
+            dst,
+            parent: self.mem_reg(mem.membrane),
+        };
+        il.push(Instr::new(new_mem, mem.span));
         if !mem.name.is_empty() {
-            il.push(IL::SetMemName(mem.id, mem.name.clone()));
+            let set_name = IL::SetMemName {
+                mem: dst,
+                name: mem.name.clone(),
+            };
+            il.push(Instr::new(set_name, mem.span));
         }
+        il.extend(self.load_rule_set(mem, dst));
         for process in &mem.process {
-            let mut unit = self.gen_unit(*process, case);
+            let mut unit = self.gen_unit(*process, dst, case);
             il.append(&mut unit);
         }
-        // for rule_id in &mem.rule_set {
-        //     let rule = unsafe { RULES.get().unwrap().get(rule_id).unwrap() };
-        //     let rule_il = gen_rule(rule);
-        //     self.emit_rule(mem.id, rule_il);
-        // }
         il
     }
+
+    /// Carry over what a process context of the head matched into the membrane in `mem`, for the
+    /// process context `id` of the body.
+    fn move_cells(&self, id: usize, mem: Reg) -> Option<Instr> {
+        let procs = &self.rule.procs;
+        let matched = procs
+            .iter()
+            .find(|head| head.name == procs[id].name && head.membrane.is_some())?;
+        let move_cells = IL::MoveCells {
+            dst: mem,
+            src: self.mem_regs[&matched.membrane?],
+        };
+        Some(Instr::new(move_cells, procs[id].span))
+    }
+
+    /// Give the membrane in `reg` the rules written in `mem`, which are generated as a new rule set.
+    fn load_rule_set(&mut self, mem: &'a Membrane, reg: Reg) -> Option<Instr> {
+        if mem.rule_set.is_empty() {
+            return None;
+        }
+        let rule_set = RuleSetId(self.first_rule_set + self.rule_sets.len());
+        self.rule_sets.push((rule_set, mem));
+        let load = IL::LoadRuleSet { mem: reg, rule_set };
+        Some(Instr::new(load, mem.span))
+    }
 }
@@ -0,0 +1,541 @@
+//! Checks that the generated IL is well formed, so a bug in the code generator or an optimizer
+//! is reported where it happens instead of as a crash of the runtime.
+//!
+//! A rule is checked from its first block, following its branches and jumps,
+//! keeping what each register holds on the way. Register 0 holds the membrane the rule runs in.
+//! Every port of the atoms a rule creates must be linked exactly once when it proceeds.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    il::{BlockId, HistoryKey, Instr, Port, Reg, IL},
+    rule_gen::RuleIL,
+    ILGenerator,
+};
+use crate::{
+    diagnostic::{Diagnostic, Span},
+    functor::FunctorTable,
+};
+
+impl ILGenerator {
+    /// Check the generated IL, returning an error for each malformed instruction.
+    pub fn verify(&self) -> Vec<Diagnostic> {
+        let mut verifier = Verifier::new(&self.functors);
+        verifier.rule(&self.init_rule, "the initial process".to_string());
+        for rule in self.rule_sets.values().flatten() {
+            verifier.rule(rule, format!("rule `{}`", rule.name));
+        }
+        verifier.diagnostics
+    }
+}
+
+impl RuleIL {
+    /// Check this rule on its own, its functors being those of `functors`.
+    pub fn verify(&self, functors: &FunctorTable) -> Vec<Diagnostic> {
+        let mut verifier = Verifier::new(functors);
+        verifier.rule(self, format!("rule `{}`", self.name));
+        verifier.diagnostics
+    }
+}
+
+/// What a register holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// An atom, with its arity when it is known.
+    Atom(Option<usize>),
+    Membrane,
+    /// A number computed in a guard.
+    Value,
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            Kind::Atom(_) => "an atom",
+            Kind::Membrane => "a membrane",
+            Kind::Value => "a value",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct State {
+    regs: HashMap<Reg, Kind>,
+    /// Registers holding an atom or a membrane that was removed from its membrane.
+    removed: HashSet<Reg>,
+    /// The atoms created so far, with the number of links made to each of their ports.
+    created: Vec<Created>,
+    /// The index in `created` of the atom each register holds, if it holds one.
+    creations: HashMap<Reg, usize>,
+}
+
+#[derive(Debug, Clone)]
+struct Created {
+    reg: Reg,
+    /// The name of its functor, none for a number.
+    name: Option<String>,
+    links: Vec<usize>,
+}
+
+impl State {
+    fn new() -> Self {
+        Self {
+            regs: HashMap::from([(Reg(0), Kind::Membrane)]),
+            removed: HashSet::new(),
+            created: Vec::new(),
+            creations: HashMap::new(),
+        }
+    }
+
+    fn write(&mut self, reg: Reg, kind: Kind) {
+        self.regs.insert(reg, kind);
+        self.removed.remove(&reg);
+        self.creations.remove(&reg);
+    }
+
+    fn create(&mut self, reg: Reg, name: Option<String>, arity: usize) {
+        self.creations.insert(reg, self.created.len());
+        self.created.push(Created {
+            reg,
+            name,
+            links: vec![0; arity],
+        });
+    }
+
+    /// Count a link made to `port` of the atom in `reg`, if the rule created it.
+    fn link(&mut self, reg: Reg, port: Port) {
+        if let Some(&i) = self.creations.get(&reg) {
+            if let Some(links) = self.created[i].links.get_mut(port.0) {
+                *links += 1;
+            }
+        }
+    }
+}
+
+/// The instruction being checked, for the errors about it.
+struct Place<'a> {
+    block: &'a str,
+    index: usize,
+    il: &'a IL,
+    span: Option<Span>,
+}
+
+struct Verifier<'a> {
+    functors: &'a FunctorTable,
+    /// Blocks of the rule being checked.
+    blocks: &'a [Vec<Instr>],
+    /// What the blocks belong to, for the errors.
+    owner: String,
+    /// Where the rule is written, for the errors about instructions without a span.
+    span: Option<Span>,
+    /// Number of history tables of the rule.
+    histories: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(functors: &'a FunctorTable) -> Self {
+        Self {
+            functors,
+            blocks: &[],
+            owner: String::new(),
+            span: None,
+            histories: 0,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    fn rule(&mut self, rule: &'a RuleIL, owner: String) {
+        self.blocks = &rule.blocks;
+        self.owner = owner;
+        self.span = Some(rule.span);
+        self.histories = rule.histories.len();
+        if !rule.blocks.is_empty() {
+            self.block(&mut State::new(), BlockId(0), &mut Vec::new());
+        }
+    }
+
+    /// Check a block entered with `state`, `path` being the blocks entered before it.
+    fn block(&mut self, state: &mut State, id: BlockId, path: &mut Vec<BlockId>) {
+        // a loop, whose blocks are already being checked
+        if path.contains(&id) {
+            return;
+        }
+        path.push(id);
+        let name = format!("block {} of {}", id, self.owner);
+        let block = &self.blocks[id.0];
+        if !self.instrs(state, block, &name, path) {
+            let place = Place {
+                block: &name,
+                index: block.len().saturating_sub(1),
+                il: block.last().map_or(&IL::Fail, |instr| &instr.il),
+                span: block.last().and_then(|instr| instr.span).or(self.span),
+            };
+            self.error(
+                "the block does not end with `jump`, `fail` or `proceed`".to_string(),
+                &place,
+            );
+        }
+        path.pop();
+    }
+
+    /// Check instructions in order, following their branches and jumps.
+    /// Returns whether they end with a jump, a failure or the end of the rule.
+    fn instrs(
+        &mut self,
+        state: &mut State,
+        block: &[Instr],
+        name: &str,
+        path: &mut Vec<BlockId>,
+    ) -> bool {
+        for (index, instr) in block.iter().enumerate() {
+            let place = Place {
+                block: name,
+                index,
+                il: &instr.il,
+                span: instr.span.or(self.span),
+            };
+            self.instr(state, &instr.il, &place);
+            match &instr.il {
+                // a failed branch goes on with what the registers held before it
+                IL::Branch { target } if self.target(*target, &place) => {
+                    self.block(&mut state.clone(), *target, path)
+                }
+                IL::Jump { target } if self.target(*target, &place) => {
+                    self.block(state, *target, path)
+                }
+                _ => {}
+            }
+            if instr.il.is_terminator() {
+                if index + 1 < block.len() {
+                    let next = Place {
+                        index: index + 1,
+                        il: &block[index + 1].il,
+                        span: block[index + 1].span.or(self.span),
+                        ..place
+                    };
+                    self.error("the instruction is never run".to_string(), &next);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the block `target` exists.
+    fn target(&mut self, target: BlockId, at: &Place) -> bool {
+        let exists = target.0 < self.blocks.len();
+        if !exists {
+            self.error(format!("block {} does not exist", target), at);
+        }
+        exists
+    }
+
+    fn instr(&mut self, state: &mut State, il: &IL, at: &Place) {
+        match il {
+            IL::NewAtom { dst, mem, functor } | IL::FindAtom { dst, mem, functor } => {
+                self.mem(state, *mem, at);
+                let functor = self.functors.get(*functor);
+                state.write(*dst, Kind::Atom(Some(functor.arity)));
+                if let IL::NewAtom { .. } = il {
+                    state.create(*dst, Some(functor.name.clone()), functor.arity);
+                }
+            }
+            IL::NewInt { dst, mem, src } | IL::NewFloat { dst, mem, src } => {
+                self.mem(state, *mem, at);
+                self.value(state, *src, at);
+                state.write(*dst, Kind::Atom(Some(1)));
+                state.create(*dst, None, 1);
+            }
+            IL::NewLink {
+                atom1,
+                port1,
+                atom2,
+                port2,
+                mem,
+            } => {
+                self.port(state, *atom1, *port1, at);
+                self.port(state, *atom2, *port2, at);
+                self.mem(state, *mem, at);
+                state.link(*atom1, *port1);
+                state.link(*atom2, *port2);
+            }
+            IL::ReLink {
+                atom1,
+                port1,
+                atom2,
+                port2,
+                mem,
+            } => {
+                self.port(state, *atom1, *port1, at);
+                // the link is taken over from an atom that is usually removed already
+                let arity = self
+                    .read(state, *atom2, at, true)
+                    .and_then(|kind| match kind {
+                        Kind::Atom(arity) => arity,
+                        kind => {
+                            self.expect(*atom2, kind, "an atom", at);
+                            None
+                        }
+                    });
+                self.check_port(*atom2, arity, *port2, at);
+                self.mem(state, *mem, at);
+                state.link(*atom1, *port1);
+            }
+            IL::NewMem { dst, parent } | IL::AnyMem { dst, parent, .. } => {
+                self.mem(state, *parent, at);
+                state.write(*dst, Kind::Membrane);
+            }
+            IL::SetMemName { mem, .. }
+            | IL::LoadRuleSet { mem, .. }
+            | IL::NAtoms { mem, .. }
+            | IL::NMems { mem, .. }
+            | IL::NoRules { mem }
+            | IL::MemName { mem, .. } => self.mem(state, *mem, at),
+            IL::DerefAtom { dst, src, port } => {
+                self.port(state, *src, *port, at);
+                state.write(*dst, Kind::Atom(None));
+            }
+            IL::Deref {
+                dst, src, src_port, ..
+            } => {
+                self.port(state, *src, *src_port, at);
+                state.write(*dst, Kind::Atom(None));
+            }
+            IL::Func { atom, functor } => {
+                let arity = self.functors.get(*functor).arity;
+                if let Some(known) = self.atom(state, *atom, at) {
+                    if known != arity {
+                        self.error(
+                            format!(
+                                "the atom of arity {} in register {} never has a functor of arity {}",
+                                known, atom, arity
+                            ),
+                            at,
+                        );
+                    }
+                }
+                // the atom has the arity of the functor once the check holds
+                state.write(*atom, Kind::Atom(Some(arity)));
+            }
+            IL::RemoveAtom { atom, mem } | IL::RemoveGround { atom, mem } => {
+                self.remove(state, *atom, Kind::Atom(None), at);
+                self.mem(state, *mem, at);
+            }
+            IL::RemoveMem { mem, parent } => {
+                self.remove(state, *mem, Kind::Membrane, at);
+                self.mem(state, *parent, at);
+            }
+            IL::FreeAtom { atom } => {
+                if let Some(kind) = self.read(state, *atom, at, true) {
+                    if !matches!(kind, Kind::Atom(_)) {
+                        self.expect(*atom, kind, "an atom", at);
+                    }
+                }
+            }
+            IL::AlterFunctor { atom, functor } => {
+                let arity = self.functors.get(*functor).arity;
+                if let Some(old) = self.atom(state, *atom, at) {
+                    if old != arity {
+                        self.error(
+                            format!(
+                                "the functor of arity {} does not fit the atom of arity {} in register {}",
+                                arity, old, atom
+                            ),
+                            at,
+                        );
+                    }
+                }
+                state.write(*atom, Kind::Atom(Some(arity)));
+            }
+            IL::MoveCells { dst, src } => {
+                self.mem(state, *dst, at);
+                // what is left in a matched membrane is moved once the membrane is removed
+                if let Some(kind) = self.read(state, *src, at, true) {
+                    if kind != Kind::Membrane {
+                        self.expect(*src, kind, "a membrane", at);
+                    }
+                }
+            }
+            IL::FreeMem { mem } => {
+                if let Some(kind) = self.read(state, *mem, at, true) {
+                    if kind != Kind::Membrane {
+                        self.expect(*mem, kind, "a membrane", at);
+                    }
+                }
+            }
+            IL::IsInt { atom }
+            | IL::IsFloat { atom }
+            | IL::IsString { atom }
+            | IL::IsUnary { atom }
+            | IL::IsGround { atom } => {
+                self.atom(state, *atom, at);
+            }
+            IL::EqAtom { lhs, rhs }
+            | IL::NeqAtom { lhs, rhs }
+            | IL::EqGround { lhs, rhs }
+            | IL::NeqGround { lhs, rhs } => {
+                self.atom(state, *lhs, at);
+                self.atom(state, *rhs, at);
+            }
+            IL::Not(il) => self.instr(state, il, at),
+            IL::LoadInt { dst, .. } | IL::LoadFloat { dst, .. } => state.write(*dst, Kind::Value),
+            IL::IntOp { dst, lhs, rhs, .. } | IL::FloatOp { dst, lhs, rhs, .. } => {
+                self.value(state, *lhs, at);
+                self.value(state, *rhs, at);
+                state.write(*dst, Kind::Value);
+            }
+            IL::IntCmp { lhs, rhs, .. } | IL::FloatCmp { lhs, rhs, .. } => {
+                self.value(state, *lhs, at);
+                self.value(state, *rhs, at);
+            }
+            IL::HistoryCheck { table, keys } | IL::HistoryAdd { table, keys } => {
+                if table.0 >= self.histories {
+                    self.error(format!("history table {} does not exist", table), at);
+                }
+                for key in keys {
+                    match key {
+                        HistoryKey::Atom(reg) => {
+                            self.atom(state, *reg, at);
+                        }
+                        HistoryKey::Value(reg) => self.value(state, *reg, at),
+                    }
+                }
+            }
+            IL::IntToFloat { dst, src } | IL::FloatToInt { dst, src } => {
+                self.value(state, *src, at);
+                state.write(*dst, Kind::Value);
+            }
+            IL::IntCall { dst, args, .. } | IL::FloatCall { dst, args, .. } => {
+                for arg in args {
+                    self.value(state, *arg, at);
+                }
+                state.write(*dst, Kind::Value);
+            }
+            IL::Proceed => self.links(state, at),
+            IL::Branch { .. } | IL::Jump { .. } | IL::Fail | IL::Commit | IL::Label(_) => {}
+        }
+    }
+
+    /// What `reg` holds, reporting a read before any write.
+    /// A removed atom or membrane may be read only if `removed` is true.
+    fn read(&mut self, state: &State, reg: Reg, at: &Place, removed: bool) -> Option<Kind> {
+        let Some(kind) = state.regs.get(&reg).copied() else {
+            self.error(format!("register {} is read before it is written", reg), at);
+            return None;
+        };
+        if !removed && state.removed.contains(&reg) {
+            self.error(
+                format!("register {} holds {} that was removed", reg, kind.name()),
+                at,
+            );
+        }
+        Some(kind)
+    }
+
+    /// The arity of the atom in `reg` if it is known.
+    fn atom(&mut self, state: &State, reg: Reg, at: &Place) -> Option<usize> {
+        match self.read(state, reg, at, false)? {
+            Kind::Atom(arity) => arity,
+            kind => {
+                self.expect(reg, kind, "an atom", at);
+                None
+            }
+        }
+    }
+
+    /// Check that `port` of the atom in `reg` exists.
+    fn port(&mut self, state: &State, reg: Reg, port: Port, at: &Place) {
+        let arity = self.atom(state, reg, at);
+        self.check_port(reg, arity, port, at);
+    }
+
+    fn check_port(&mut self, reg: Reg, arity: Option<usize>, port: Port, at: &Place) {
+        if let Some(arity) = arity {
+            if port.0 >= arity {
+                self.error(
+                    format!(
+                        "port {} does not exist on the atom of arity {} in register {}",
+                        port, arity, reg
+                    ),
+                    at,
+                );
+            }
+        }
+    }
+
+    fn mem(&mut self, state: &State, reg: Reg, at: &Place) {
+        if let Some(kind) = self.read(state, reg, at, false) {
+            if kind != Kind::Membrane {
+                self.expect(reg, kind, "a membrane", at);
+            }
+        }
+    }
+
+    /// A guard computes on the values of data atoms as well as its own values.
+    fn value(&mut self, state: &State, reg: Reg, at: &Place) {
+        if let Some(Kind::Membrane) = self.read(state, reg, at, false) {
+            self.expect(reg, Kind::Membrane, "a value", at);
+        }
+    }
+
+    fn remove(&mut self, state: &mut State, reg: Reg, expected: Kind, at: &Place) {
+        if state.removed.contains(&reg) {
+            self.error(format!("register {} is removed twice", reg), at);
+        } else if let Some(kind) = self.read(state, reg, at, false) {
+            let same = matches!(
+                (kind, expected),
+                (Kind::Atom(_), Kind::Atom(_)) | (Kind::Membrane, Kind::Membrane)
+            );
+            if !same {
+                self.expect(reg, kind, expected.name(), at);
+            }
+        }
+        state.removed.insert(reg);
+    }
+
+    /// Check that every port of the atoms created on the way is linked exactly once.
+    fn links(&mut self, state: &State, at: &Place) {
+        for created in &state.created {
+            for (port, &links) in created.links.iter().enumerate() {
+                if links != 1 {
+                    let atom = match &created.name {
+                        Some(name) => format!("the atom `{}`", name),
+                        None => "the number".to_string(),
+                    };
+                    self.error(
+                        format!(
+                            "port {} of {} created in register {} is linked {} times",
+                            port, atom, created.reg, links
+                        ),
+                        at,
+                    );
+                }
+            }
+        }
+    }
+
+    fn expect(&mut self, reg: Reg, found: Kind, expected: &str, at: &Place) {
+        self.error(
+            format!(
+                "register {} holds {} where {} is expected",
+                reg,
+                found.name(),
+                expected
+            ),
+            at,
+        );
+    }
+
+    fn error(&mut self, message: String, at: &Place) {
+        let il = at.il.display(self.functors).to_string();
+        let il = il.split_whitespace().collect::<Vec<_>>().join(" ");
+        let mut diagnostic = Diagnostic::error(format!("malformed IL: {}", message)).with_note(
+            format!("in instruction {} of {}: `{}`", at.index, at.block, il),
+        );
+        if let Some(span) = at.span {
+            diagnostic = diagnostic.with_label(span, "generated from here");
+        }
+        self.diagnostics.push(diagnostic);
+    }
+}
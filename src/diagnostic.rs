@@ -0,0 +1,155 @@
+use std::fmt::Write;
+
+use colored::Colorize;
+
+use crate::{parser::ParseRule, source::SourceMap};
+
+/// A byte range in the [`SourceMap`] of the program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A highlighted piece of source text attached to a diagnostic.
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A message reported by the compiler, pointing to one or more places in the source.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            message: message.into(),
+            labels: vec![],
+            notes: vec![],
+            help: None,
+        }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            ..Self::error(message)
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn with_help(mut self, help: impl Into<String>) -> Self {
+        self.help = Some(help.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Render this diagnostic against the source files it was reported for.
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let mut out = String::new();
+        let header = match self.severity {
+            Severity::Error => "error".red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        _ = writeln!(out, "{}: {}", header, self.message.bold());
+
+        for label in &self.labels {
+            let Some(file) = sources.lookup(label.span.start) else {
+                continue;
+            };
+            let source = file.text.as_str();
+            let (line, col) = line_col(source, label.span.start - file.start);
+            let text = source.lines().nth(line - 1).unwrap_or("");
+            let width = line.to_string().len();
+            let len = label
+                .span
+                .end
+                .saturating_sub(label.span.start)
+                .min(text.len().saturating_sub(col - 1))
+                .max(1);
+            _ = writeln!(
+                out,
+                "{:width$}{} {}:{}:{}",
+                "",
+                "-->".blue(),
+                file.path.display(),
+                line,
+                col
+            );
+            _ = writeln!(out, "{:width$} {}", "", "|".blue());
+            _ = writeln!(out, "{} {} {}", line.to_string().blue(), "|".blue(), text);
+            _ = writeln!(
+                out,
+                "{:width$} {} {:col$}{} {}",
+                "",
+                "|".blue(),
+                "",
+                "^".repeat(len).red(),
+                label.message,
+                col = col - 1
+            );
+        }
+        for note in &self.notes {
+            _ = writeln!(out, "{} {}", "note:".bold(), note);
+        }
+        if let Some(help) = &self.help {
+            _ = writeln!(out, "{} {}", "help:".bold(), help);
+        }
+        out
+    }
+}
+
+impl From<pest::error::Error<ParseRule>> for Diagnostic {
+    fn from(err: pest::error::Error<ParseRule>) -> Self {
+        let span = match err.location {
+            pest::error::InputLocation::Pos(pos) => Span::new(pos, pos + 1),
+            pest::error::InputLocation::Span((start, end)) => Span::new(start, end),
+        };
+        Diagnostic::error(err.variant.message()).with_label(span, "")
+    }
+}
+
+/// Convert a byte offset into a 1-based line and column.
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let col = before.len() - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+    (line, col)
+}
@@ -0,0 +1,106 @@
+//! Functors of the atoms of a program, interned into compact ids.
+
+use std::{collections::HashMap, fmt::Display};
+
+/// A functor of a program, referred to by its index in the [`FunctorTable`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FunctorId(pub usize);
+
+impl Display for FunctorId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What an atom with a functor is.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub enum FunctorKind {
+    Symbol,
+    Int,
+    Float,
+}
+
+impl FunctorKind {
+    /// The kind of the atoms with the given name.
+    fn of(name: &str) -> Self {
+        if !name.starts_with(|c: char| c.is_ascii_digit()) {
+            FunctorKind::Symbol
+        } else if name.contains('.') {
+            FunctorKind::Float
+        } else {
+            FunctorKind::Int
+        }
+    }
+}
+
+/// The name and arity of an atom.
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Functor {
+    pub name: String,
+    pub arity: usize,
+    pub kind: FunctorKind,
+}
+
+impl Functor {
+    /// Whether atoms with this functor are data such as numbers.
+    pub fn is_data(&self) -> bool {
+        self.kind != FunctorKind::Symbol
+    }
+}
+
+impl Display for Functor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.name, self.arity)
+    }
+}
+
+/// Every functor of a program, each stored once and referred to by its id.
+///
+/// Ids are given in the order the functors are first seen.
+#[derive(Debug, Default)]
+pub struct FunctorTable {
+    functors: Vec<Functor>,
+    /// Ids of the functors with each name, looked up without allocating the name.
+    ids: HashMap<String, Vec<FunctorId>>,
+}
+
+impl FunctorTable {
+    /// The id of the functor `name/arity`, added if it is not in the table yet.
+    pub fn intern(&mut self, name: &str, arity: usize) -> FunctorId {
+        let found = self.ids.get(name).and_then(|ids| {
+            ids.iter()
+                .find(|id| self.functors[id.0].arity == arity)
+                .copied()
+        });
+        if let Some(id) = found {
+            return id;
+        }
+        let id = FunctorId(self.functors.len());
+        self.functors.push(Functor {
+            name: name.to_string(),
+            arity,
+            kind: FunctorKind::of(name),
+        });
+        self.ids.entry(name.to_string()).or_default().push(id);
+        id
+    }
+
+    pub fn get(&self, id: FunctorId) -> &Functor {
+        &self.functors[id.0]
+    }
+
+    pub fn len(&self) -> usize {
+        self.functors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.functors.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (FunctorId, &Functor)> {
+        self.functors
+            .iter()
+            .enumerate()
+            .map(|(id, functor)| (FunctorId(id), functor))
+    }
+}
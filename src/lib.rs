@@ -1,6 +1,15 @@
+// The parser keeps its tables in `static mut`s and is only ever driven from one thread.
+#![allow(static_mut_refs)]
+
+pub mod analysis;
 pub mod codegen;
+pub mod diagnostic;
+pub mod functor;
+pub mod manifest;
 pub mod optimizer;
 pub mod parser;
+pub mod source;
+pub mod stdlib;
 pub mod util;
 
 extern crate pest;
@@ -1,27 +1,210 @@
-use liblmntalc::codegen;
+use std::{io::Write, path::Path, process::ExitCode};
+
+use clap::Parser;
+use liblmntalc::{
+    analysis::{self, AnalysisOptions},
+    codegen::{self, ILGenerator, Target},
+    manifest::{self, Manifest},
+    optimizer::OptimizerManager,
+    parser::{data::Symbol, ParseOptions},
+    source::SourceMap,
+    util,
+};
+use options::{Args, Command};
 
 mod options;
 
-fn main() {
-    match liblmntalc::parser::parse_lmntal(
-        r"
-a(10), b(20);
-case_rule:
-a(X), b(Y)
-when int(X) && int(Y);
-    with Z := X + Y;
-    then c(Z);
-when float(X) && float(Y);
-    with W := X - Y;
-    then c(W);
-    ",
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Build) => build(args),
+        None => compile_file(args),
+    }
+}
+
+/// Compile a single file, writing the IL to `--output` or stdout.
+fn compile_file(args: Args) -> ExitCode {
+    let input = args.input.as_deref().unwrap();
+    let options = ParseOptions {
+        include_paths: args.include_paths,
+        // a later definition of the same name wins
+        constants: args.defines.into_iter().collect(),
+    };
+    let target = args.emit.unwrap_or_default();
+    if args.annotate && target != Target::Text {
+        eprintln!("`--annotate` only applies to the text IL");
+        return ExitCode::FAILURE;
+    }
+    let optimizers = match optimizers(args.optimize_level.unwrap_or_default(), &args.disables) {
+        Ok(optimizers) => optimizers,
+        Err(code) => return code,
+    };
+    let (gen, sources) = match compile(input, &options, &optimizers, args.overloads, args.dump_ast)
+    {
+        Ok(compiled) => compiled,
+        Err(code) => return code,
+    };
+    if target == Target::Text && args.output.is_some() {
+        colored::control::set_override(false);
+    }
+    let written = match &args.output {
+        Some(path) => std::fs::write(path, emit(&gen, target, &sources, args.annotate)),
+        None => std::io::stdout().write_all(&emit(&gen, target, &sources, args.annotate)),
+    };
+    if let Err(e) = written {
+        eprintln!("cannot write the output: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
+
+/// Compile the project in the current directory, writing each target into its build directory.
+fn build(args: Args) -> ExitCode {
+    let mut manifest = match Manifest::load(Path::new(".")) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("{}", e);
+            eprintln!(
+                "`build` needs a {} in the current directory",
+                manifest::FILE_NAME
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+    if !args.include_paths.is_empty() {
+        manifest.include_paths = args.include_paths;
+    }
+    if let Some(level) = args.optimize_level {
+        manifest.optimize_level = level;
+    }
+    if !args.disables.is_empty() {
+        manifest.disables = args.disables;
+    }
+    if let Some(target) = args.emit {
+        manifest.emit = vec![target];
+    }
+
+    let options = ParseOptions {
+        include_paths: manifest.include_paths,
+        constants: args.defines.into_iter().collect(),
+    };
+    let optimizers = match optimizers(manifest.optimize_level, &manifest.disables) {
+        Ok(optimizers) => optimizers,
+        Err(code) => return code,
+    };
+    let (gen, sources) = match compile(
+        &manifest.entry,
+        &options,
+        &optimizers,
+        args.overloads,
+        args.dump_ast,
     ) {
-        Ok(s) => {
-            // print_result(&s, 0);
-            let mut gen = codegen::ILGenerator::default();
-            gen.gen(s);
-            print!("{}", gen);
+        Ok(compiled) => compiled,
+        Err(code) => return code,
+    };
+    if let Err(e) = std::fs::create_dir_all(&manifest.build_dir) {
+        eprintln!("cannot create {}: {}", manifest.build_dir.display(), e);
+        return ExitCode::FAILURE;
+    }
+    colored::control::set_override(false);
+    let stem = manifest.entry.file_stem().unwrap_or_default();
+    for target in manifest.emit {
+        let path = manifest
+            .build_dir
+            .join(stem)
+            .with_extension(target.extension());
+        if let Err(e) = std::fs::write(&path, emit(&gen, target, &sources, args.annotate)) {
+            eprintln!("cannot write {}: {}", path.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+/// The optimizers enabled up to `level`, without the ones named in `disables`.
+fn optimizers(level: u8, disables: &[String]) -> Result<OptimizerManager, ExitCode> {
+    let mut optimizers = OptimizerManager::with_defaults(level);
+    for name in disables {
+        if let Err(names) = optimizers.disable(name) {
+            eprintln!(
+                "no optimizer is named `{}`, the optimizers are {}",
+                name,
+                names.join(", ")
+            );
+            return Err(ExitCode::FAILURE);
         }
-        Err(e) => println!("{}", e),
+    }
+    Ok(optimizers)
+}
+
+/// Parse, check, generate and optimize with `optimizers` the IL of the program in `input`,
+/// printing its diagnostics. The source files of the program are returned with its IL.
+///
+/// Fails with the exit code to return, which is a success after dumping the AST.
+fn compile(
+    input: &Path,
+    options: &ParseOptions,
+    optimizers: &OptimizerManager,
+    overloads: Vec<String>,
+    dump_ast: bool,
+) -> Result<(ILGenerator, SourceMap), ExitCode> {
+    let source = match std::fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", input.display(), e);
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    let mut sources = SourceMap::new();
+    let file = sources.add(input, source);
+    let s = match liblmntalc::parser::parse_lmntal(&mut sources, file, options) {
+        Ok(s) => s,
+        Err(diagnostics) => {
+            for d in diagnostics {
+                eprint!("{}", d.render(&sources));
+            }
+            return Err(ExitCode::FAILURE);
+        }
+    };
+    let Symbol::Membrane(root) = s else {
+        unreachable!()
+    };
+    if dump_ast {
+        util::print_constants(&options.constants);
+        util::print_result(&s, 0);
+        return Err(ExitCode::SUCCESS);
+    }
+    let diagnostics = analysis::analyze(root, &AnalysisOptions { overloads });
+    for d in &diagnostics {
+        eprint!("{}", d.render(&sources));
+    }
+    if diagnostics.iter().any(|d| d.is_error()) {
+        return Err(ExitCode::FAILURE);
+    }
+    let mut gen = codegen::ILGenerator::default();
+    gen.gen(s);
+    let mut malformed = gen.verify();
+    if malformed.is_empty() {
+        if let Err(errors) = optimizers.optimize(&mut gen) {
+            malformed = errors;
+        }
+    }
+    if !malformed.is_empty() {
+        for d in &malformed {
+            eprint!("{}", d.render(&sources));
+        }
+        return Err(ExitCode::FAILURE);
+    }
+    Ok((gen, sources))
+}
+
+/// Encode the IL in `target`, annotating the text IL with the lines of `sources` if `annotate`.
+///
+/// The binary IL always keeps its spans, along with the files of `sources` they point into.
+fn emit(gen: &ILGenerator, target: Target, sources: &SourceMap, annotate: bool) -> Vec<u8> {
+    match target {
+        Target::Text if annotate => gen.annotated(sources).to_string().into_bytes(),
+        Target::Text => gen.to_string().into_bytes(),
+        Target::Binary => gen.to_binary(sources),
     }
 }
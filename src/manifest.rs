@@ -0,0 +1,66 @@
+//! The `lmntal.toml` manifest of a project.
+//!
+//! ```toml
+//! entry = "src/main.lmn"
+//! include-paths = ["lib"]
+//! optimize-level = 1
+//! disables = []
+//! emit = ["text", "binary"]
+//! build-dir = "build"
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::codegen::Target;
+
+/// Name of the manifest file of a project.
+pub const FILE_NAME: &str = "lmntal.toml";
+
+/// Settings of a project, paths are relative to the directory of the manifest.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Manifest {
+    /// The file the program starts from.
+    pub entry: PathBuf,
+    /// Directories to search for included files and used modules.
+    #[serde(default)]
+    pub include_paths: Vec<PathBuf>,
+    #[serde(default)]
+    pub optimize_level: u8,
+    /// Names of the optimizers to turn off.
+    #[serde(default)]
+    pub disables: Vec<String>,
+    /// Formats to write the IL in, one file each.
+    #[serde(default = "default_emit")]
+    pub emit: Vec<Target>,
+    /// Directory the outputs are written to.
+    #[serde(default = "default_build_dir")]
+    pub build_dir: PathBuf,
+}
+
+fn default_emit() -> Vec<Target> {
+    vec![Target::Text]
+}
+
+fn default_build_dir() -> PathBuf {
+    PathBuf::from("build")
+}
+
+impl Manifest {
+    /// Read the manifest in `dir`, with its paths resolved against `dir`.
+    pub fn load(dir: &Path) -> Result<Self, String> {
+        let path = dir.join(FILE_NAME);
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let mut manifest: Manifest =
+            toml::from_str(&text).map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+        manifest.entry = dir.join(&manifest.entry);
+        for include in &mut manifest.include_paths {
+            *include = dir.join(&*include);
+        }
+        manifest.build_dir = dir.join(&manifest.build_dir);
+        Ok(manifest)
+    }
+}
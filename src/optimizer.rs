@@ -1,16 +1,30 @@
+pub mod pattern_optimizer;
 pub mod rule_optimizer;
 
 use std::{collections::BTreeSet, fmt::Debug};
 
-use crate::codegen::ILGenerator;
+use crate::{codegen::ILGenerator, diagnostic::Diagnostic};
 
 pub trait Optimizer: Debug {
     /// Optimize the given IL.
     fn optimize(&self, il: &mut ILGenerator);
 
     /// Unique ID of this optimizer. Used to determine whether two optimizers are the same.
+    ///
+    /// Defaults to a hash of the name of the type, so it is the same in every run.
     fn uid(&self) -> u32 {
-        rand::random()
+        // FNV-1a
+        std::any::type_name::<Self>()
+            .bytes()
+            .fold(0x811c9dc5, |hash, b| {
+                (hash ^ b as u32).wrapping_mul(0x01000193)
+            })
+    }
+
+    /// Name of this optimizer, the name of its type.
+    fn name(&self) -> &'static str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
     }
 
     /// The total number of times this optimizer is executed.
@@ -41,13 +55,14 @@ impl PartialEq for Box<dyn Optimizer> {
 
 impl PartialOrd for Box<dyn Optimizer> {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        self.order().partial_cmp(&other.order())
+        Some(self.cmp(other))
     }
 }
 
+/// By order, then by id, so optimizers of the same order are all kept, consistently with `eq`.
 impl Ord for Box<dyn Optimizer> {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.order().cmp(&other.order())
+        (self.order(), self.uid()).cmp(&(other.order(), other.uid()))
     }
 }
 
@@ -67,18 +82,55 @@ impl OptimizerManager {
         }
     }
 
-    pub fn optimize(&self, il: &mut ILGenerator) {
+    /// The optimizers of the compiler, enabled up to `level`.
+    pub fn with_defaults(level: u8) -> Self {
+        let mut manager = Self::new(level);
+        // the head is matched before the atoms it removes are reused
+        let mut pattern = pattern_optimizer::PatternOptimizer::default();
+        pattern.set_order(0);
+        manager.add_optimizer(Box::new(pattern));
+        let mut reuse = rule_optimizer::RuleOptimizer::default();
+        reuse.set_order(1);
+        manager.add_optimizer(Box::new(reuse));
+        manager
+    }
+
+    /// Run the enabled optimizers, checking the IL after each of them.
+    ///
+    /// Fails with the errors of the verifier after the first optimizer that leaves malformed IL.
+    pub fn optimize(&self, il: &mut ILGenerator) -> Result<(), Vec<Diagnostic>> {
         for i in 1..=self.pass {
             for optimizer in self.optimizers.iter() {
                 if optimizer.pass() >= i && optimizer.level() <= self.level {
                     optimizer.optimize(il);
+                    let malformed = il.verify();
+                    if !malformed.is_empty() {
+                        let note = format!("after the optimizer `{}`", optimizer.name());
+                        return Err(malformed
+                            .into_iter()
+                            .map(|d| d.with_note(note.clone()))
+                            .collect());
+                    }
                 }
             }
         }
+        Ok(())
     }
 
     pub fn add_optimizer(&mut self, optimizer: Box<dyn Optimizer>) {
         self.pass = u8::max(self.pass, optimizer.pass());
         self.optimizers.insert(optimizer);
     }
+
+    /// Turn off the optimizer named `name`, as given by [`Optimizer::name`].
+    ///
+    /// Fails with the names of the optimizers if none is named so.
+    pub fn disable(&mut self, name: &str) -> Result<(), Vec<&'static str>> {
+        let before = self.optimizers.len();
+        self.optimizers.retain(|optimizer| optimizer.name() != name);
+        if self.optimizers.len() == before {
+            return Err(self.optimizers.iter().map(|o| o.name()).collect());
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,585 @@
+//! Matching order: a rule finds one atom of its head, the anchor, and reaches the others through
+//! their links instead of finding each of them on its own, checking the type of a linked value
+//! as soon as the atom it hangs from is matched.
+//!
+//! The head is made of checks that change nothing, so they can run in any order that keeps each
+//! register written before it is read. The analysis rejects links that leave a membrane without a
+//! proxy, in the initial process as in rule heads and bodies, so an atom reached through a link is
+//! in the membrane it would be found in.
+
+use std::collections::{HashMap, HashSet};
+
+use super::Optimizer;
+use crate::codegen::{
+    il::{Instr, Port, Reg, IL},
+    liveness::Liveness,
+    ILGenerator,
+};
+use crate::functor::{FunctorId, FunctorTable};
+
+/// Orders the matching of each rule head, starting from its most selective atom.
+#[derive(Debug, Default)]
+pub struct PatternOptimizer {
+    order: i32,
+}
+
+impl Optimizer for PatternOptimizer {
+    fn optimize(&self, il: &mut ILGenerator) {
+        let created = created_atoms(il);
+        let (functors, rules) = il.rules_mut();
+        for rule in rules {
+            if rule.blocks.is_empty() {
+                continue;
+            }
+            let mut next = rule.registers;
+            let hoisted = hoist_type_checks(&mut rule.blocks, &mut next);
+            let Some(end) = rule.blocks[0]
+                .iter()
+                .position(|instr| matches!(instr.il, IL::Branch { .. }))
+            else {
+                continue;
+            };
+            let links = link_checks(&rule.blocks, end);
+            let mut head = Head::new(&rule.blocks[0][..end], links, hoisted, &mut next);
+            let mut block = head.schedule(&Selectivity {
+                functors,
+                created: &created,
+            });
+            block.extend_from_slice(&rule.blocks[0][end..]);
+            rule.blocks[0] = block;
+            rule.allocate_registers();
+        }
+    }
+
+    fn pass(&self) -> u8 {
+        1
+    }
+
+    fn level(&self) -> u8 {
+        1
+    }
+
+    fn order(&self) -> i32 {
+        self.order
+    }
+
+    fn set_order(&mut self, order: i32) {
+        self.order = order;
+    }
+}
+
+/// How many atoms of each functor the program creates, in its initial process and rule bodies.
+fn created_atoms(il: &ILGenerator) -> HashMap<FunctorId, usize> {
+    let mut created = HashMap::new();
+    for rule in il.rules() {
+        for instr in rule.blocks.iter().flatten() {
+            if let IL::NewAtom { functor, .. } | IL::AlterFunctor { functor, .. } = instr.il {
+                *created.entry(functor).or_default() += 1;
+            }
+        }
+    }
+    created
+}
+
+/// What makes an atom of the head a good anchor, the atoms with fewer candidates first.
+struct Selectivity<'a> {
+    functors: &'a FunctorTable,
+    created: &'a HashMap<FunctorId, usize>,
+}
+
+impl Selectivity<'_> {
+    /// The rarest functor first, then an atom in a membrane of the head, which holds fewer atoms
+    /// than the membrane of the rule, then a data atom, whose value is written in the head.
+    fn key(&self, find: &Find) -> (usize, bool, bool) {
+        (
+            self.created.get(&find.functor).copied().unwrap_or_default(),
+            find.mem == Reg(0),
+            !self.functors.get(find.functor).is_data(),
+        )
+    }
+}
+
+/// A type check moved from every case into the head: the atom loaded from `port` of `src`
+/// into `dst`, and the checks on it.
+struct Hoisted {
+    load: Instr,
+    checks: Vec<Instr>,
+}
+
+/// Move the type checks every case of a rule makes on a value linked to the head into the head,
+/// giving the value the register `next`.
+fn hoist_type_checks(blocks: &mut [Vec<Instr>], next: &mut usize) -> Vec<Hoisted> {
+    let Some((head, cases)) = blocks.split_first_mut() else {
+        return Vec::new();
+    };
+    let targets: Vec<usize> = head
+        .iter()
+        .filter_map(|instr| match instr.il {
+            IL::Branch { target } => Some(target.0),
+            _ => None,
+        })
+        .collect();
+    // only cases entered from the head and running straight to the body
+    let straight = cases.iter().enumerate().all(|(i, block)| {
+        targets.contains(&(i + 1))
+            && !block
+                .iter()
+                .any(|instr| matches!(instr.il, IL::Branch { .. } | IL::Jump { .. }))
+    });
+    if targets.is_empty() || !straight {
+        return Vec::new();
+    }
+    let head_regs: HashSet<Reg> = head.iter().filter_map(|instr| instr.il.writes()).collect();
+
+    let guards: Vec<Vec<TypeGuard>> = cases
+        .iter()
+        .map(|block| type_guards(block, &head_regs))
+        .collect();
+    let mut hoisted = Vec::new();
+    for guard in &guards[0] {
+        // the checks of this value every case makes
+        let mut checks: Vec<&Instr> = guard.checks.iter().map(|&k| &cases[0][k]).collect();
+        for (block, guards) in cases.iter().zip(&guards).skip(1) {
+            let same = guards.iter().find(|g| g.source == guard.source);
+            checks.retain(|check| {
+                same.is_some_and(|same| {
+                    same.checks
+                        .iter()
+                        .any(|&k| same_check(&block[k].il, &check.il))
+                })
+            });
+        }
+        if checks.is_empty() {
+            continue;
+        }
+        let value = Reg(*next);
+        *next += 1;
+        let (src, port) = guard.source;
+        let load = Instr {
+            il: IL::DerefAtom {
+                dst: value,
+                src,
+                port,
+            },
+            span: cases[0][guard.load].span,
+        };
+        let checks = checks
+            .into_iter()
+            .map(|check| {
+                let mut check = check.clone();
+                check
+                    .il
+                    .map_regs(&mut |reg| if reg == guard.value { value } else { reg });
+                check
+            })
+            .collect();
+        hoisted.push(Hoisted { load, checks });
+    }
+
+    for (block, guards) in cases.iter_mut().zip(&guards) {
+        let mut dropped = HashSet::new();
+        // register read in place of another from the instruction after the load until it is written
+        let mut renames = Vec::new();
+        for hoisted in &hoisted {
+            let IL::DerefAtom { dst, src, port, .. } = hoisted.load.il else {
+                unreachable!()
+            };
+            let guard = guards.iter().find(|g| g.source == (src, port)).unwrap();
+            dropped.insert(guard.load);
+            for &k in &guard.checks {
+                let check = hoisted.checks.iter().any(|check| {
+                    let mut mapped = block[k].il.clone();
+                    mapped.map_regs(&mut |reg| if reg == guard.value { dst } else { reg });
+                    same_check(&mapped, &check.il)
+                });
+                if check {
+                    dropped.insert(k);
+                }
+            }
+            renames.push((guard.load, guard.value, dst));
+        }
+        for (load, from, to) in renames {
+            for instr in &mut block[load + 1..] {
+                let written = instr.il.writes() == Some(from);
+                instr
+                    .il
+                    .map_regs(&mut |reg| if reg == from { to } else { reg });
+                if written {
+                    // the register is written again, and holds another value from here on
+                    *instr.il.writes_mut().unwrap() = from;
+                    break;
+                }
+            }
+        }
+        let mut k = 0;
+        block.retain(|_| {
+            k += 1;
+            !dropped.contains(&(k - 1))
+        });
+    }
+    hoisted
+}
+
+/// A value linked to the head that a case loads before it commits, and the type checks on it.
+struct TypeGuard {
+    /// The atom of the head and the port the value is linked to.
+    source: (Reg, Port),
+    /// Index of the load in the block, and the register it loads the value into.
+    load: usize,
+    value: Reg,
+    /// Indices of the type checks on the value.
+    checks: Vec<usize>,
+}
+
+fn type_guards(block: &[Instr], head_regs: &HashSet<Reg>) -> Vec<TypeGuard> {
+    let mut guards: Vec<TypeGuard> = Vec::new();
+    // the guards whose value is still in its register
+    let mut open: Vec<usize> = Vec::new();
+    let mut written = HashSet::new();
+    for (k, instr) in block.iter().enumerate() {
+        match instr.il {
+            IL::Commit => break,
+            IL::IsInt { atom }
+            | IL::IsFloat { atom }
+            | IL::IsString { atom }
+            | IL::IsUnary { atom }
+            | IL::IsGround { atom } => {
+                if let Some(&g) = open.iter().find(|&&g| guards[g].value == atom) {
+                    guards[g].checks.push(k);
+                }
+            }
+            _ => {}
+        }
+        let load = match instr.il {
+            IL::DerefAtom { dst, src, port }
+                if head_regs.contains(&src) && !written.contains(&src) =>
+            {
+                Some(TypeGuard {
+                    source: (src, port),
+                    load: k,
+                    value: dst,
+                    checks: Vec::new(),
+                })
+            }
+            _ => None,
+        };
+        if let Some(reg) = instr.il.writes() {
+            open.retain(|&g| guards[g].value != reg);
+            written.insert(reg);
+        }
+        if let Some(load) = load {
+            open.push(guards.len());
+            guards.push(load);
+        }
+    }
+    guards
+}
+
+/// Whether two type checks are the same check of the same register.
+fn same_check(a: &IL, b: &IL) -> bool {
+    std::mem::discriminant(a) == std::mem::discriminant(b) && a.reads() == b.reads()
+}
+
+/// Indices in the first block, before `end`, of the `deref` each link of the head is checked by,
+/// followed by the `eqatom` comparing the atom it reaches with the other end of the link.
+fn link_checks(blocks: &[Vec<Instr>], end: usize) -> HashSet<usize> {
+    let head = &blocks[0];
+    let mut checks = HashSet::new();
+    Liveness::analyze(blocks).scan(blocks, 0, |index, live_out, instr| {
+        if index == 0 || index >= end {
+            return;
+        }
+        let (IL::EqAtom { lhs, .. }, IL::Deref { dst, .. }) = (&instr.il, &head[index - 1].il)
+        else {
+            return;
+        };
+        // the atom reached is compared and forgotten
+        if lhs == dst && !live_out.contains(lhs) {
+            checks.insert(index - 1);
+        }
+    });
+    checks
+}
+
+/// An atom of the head found with `find_atom`.
+struct Find {
+    dst: Reg,
+    mem: Reg,
+    functor: FunctorId,
+}
+
+/// A part of the head to schedule.
+enum Step {
+    /// Finds an atom.
+    Find(Instr),
+    /// Checks that `port1` of `atom1` is linked to `port2` of `atom2`, with a `deref` and an
+    /// `eqatom`, or reaches one atom from the other once only one of them is found.
+    Link {
+        deref: Instr,
+        eq: Instr,
+        atom1: Reg,
+        port1: Port,
+        atom2: Reg,
+        port2: Port,
+    },
+    /// Any other instruction, run as soon as it can.
+    Other(Instr),
+}
+
+impl Step {
+    fn instrs(&self) -> Vec<&Instr> {
+        match self {
+            Step::Find(instr) | Step::Other(instr) => vec![instr],
+            Step::Link { deref, eq, .. } => vec![deref, eq],
+        }
+    }
+
+    fn find(&self) -> Option<Find> {
+        match self {
+            Step::Find(Instr {
+                il: IL::FindAtom { dst, mem, functor },
+                ..
+            }) => Some(Find {
+                dst: *dst,
+                mem: *mem,
+                functor: *functor,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// The head of a rule, as steps that each wait for the steps before them they depend on.
+struct Head {
+    steps: Vec<Step>,
+    /// Step -> the steps before it in the head it must come after
+    deps: Vec<Vec<usize>>,
+    placed: Vec<bool>,
+    block: Vec<Instr>,
+}
+
+impl Head {
+    /// The atom a link check reaches is given a register from `next`, so reusing its register
+    /// does not tie the check to the other instructions.
+    fn new(head: &[Instr], links: HashSet<usize>, hoisted: Vec<Hoisted>, next: &mut usize) -> Self {
+        let mut steps = Vec::new();
+        let mut k = 0;
+        while k < head.len() {
+            let instr = &head[k];
+            match (links.contains(&k), &instr.il) {
+                (
+                    true,
+                    &IL::Deref {
+                        src,
+                        src_port,
+                        dst_port,
+                        ..
+                    },
+                ) => {
+                    let IL::EqAtom { lhs, rhs } = head[k + 1].il else {
+                        unreachable!()
+                    };
+                    let reached = Reg(*next);
+                    *next += 1;
+                    let (mut deref, mut eq) = (instr.clone(), head[k + 1].clone());
+                    for instr in [&mut deref, &mut eq] {
+                        instr
+                            .il
+                            .map_regs(&mut |reg| if reg == lhs { reached } else { reg });
+                    }
+                    steps.push(Step::Link {
+                        deref,
+                        eq,
+                        atom1: src,
+                        port1: src_port,
+                        atom2: rhs,
+                        port2: dst_port,
+                    });
+                    k += 1;
+                }
+                (_, IL::FindAtom { .. }) => steps.push(Step::Find(instr.clone())),
+                _ => steps.push(Step::Other(instr.clone())),
+            }
+            k += 1;
+        }
+        for hoisted in hoisted {
+            steps.push(Step::Other(hoisted.load));
+            steps.extend(hoisted.checks.into_iter().map(Step::Other));
+        }
+
+        // a step comes after the steps writing what it reads,
+        // and after the steps reading or writing what it writes
+        let access: Vec<(HashSet<Reg>, HashSet<Reg>)> = steps
+            .iter()
+            .map(|step| {
+                let instrs = step.instrs();
+                let reads = instrs.iter().flat_map(|instr| instr.il.reads()).collect();
+                let writes = instrs
+                    .iter()
+                    .filter_map(|instr| instr.il.writes())
+                    .collect();
+                (reads, writes)
+            })
+            .collect();
+        let deps = (0..steps.len())
+            .map(|j| {
+                let (reads, writes) = &access[j];
+                (0..j)
+                    .filter(|&i| {
+                        let (before_reads, before_writes) = &access[i];
+                        !before_writes.is_disjoint(reads)
+                            || !before_reads.is_disjoint(writes)
+                            || !before_writes.is_disjoint(writes)
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            placed: vec![false; steps.len()],
+            steps,
+            deps,
+            block: Vec::new(),
+        }
+    }
+
+    /// Whether the step `i` can run, the step `reached` left aside.
+    fn ready(&self, i: usize, reached: Option<usize>) -> bool {
+        !self.placed[i]
+            && self.deps[i]
+                .iter()
+                .all(|&d| self.placed[d] || Some(d) == reached)
+    }
+
+    /// Order the head, returning its instructions.
+    fn schedule(&mut self, selectivity: &Selectivity) -> Vec<Instr> {
+        loop {
+            self.place_checks();
+            if !self.reach(selectivity) && !self.anchor(selectivity) {
+                break;
+            }
+        }
+        // what could not be ordered keeps the order it had
+        for i in 0..self.steps.len() {
+            if !self.placed[i] {
+                self.place(i);
+            }
+        }
+        std::mem::take(&mut self.block)
+    }
+
+    fn place(&mut self, i: usize) {
+        self.placed[i] = true;
+        let instrs = self.steps[i].instrs().into_iter().cloned();
+        self.block.extend(instrs);
+    }
+
+    /// Run every check whose registers are written, and every link check between found atoms.
+    fn place_checks(&mut self) {
+        let mut progress = true;
+        while progress {
+            progress = false;
+            for i in 0..self.steps.len() {
+                if !matches!(self.steps[i], Step::Find(_)) && self.ready(i, None) {
+                    self.place(i);
+                    progress = true;
+                }
+            }
+        }
+    }
+
+    /// Reach an atom linked to one already found, the most selective first.
+    fn reach(&mut self, selectivity: &Selectivity) -> bool {
+        let mut best: Option<(usize, usize, bool)> = None;
+        let mut best_key = None;
+        for (i, step) in self.steps.iter().enumerate() {
+            let Step::Link { atom1, atom2, .. } = *step else {
+                continue;
+            };
+            for (from, to, forward) in [(atom1, atom2, true), (atom2, atom1, false)] {
+                let Some(found) = self.find_of(from).filter(|&f| self.placed[f]) else {
+                    continue;
+                };
+                let Some(target) = self.find_of(to) else {
+                    continue;
+                };
+                let (found, target_find) = (
+                    self.steps[found].find().unwrap(),
+                    self.steps[target].find().unwrap(),
+                );
+                if found.mem != target_find.mem
+                    || !self.ready(target, None)
+                    || !self.ready(i, Some(target))
+                {
+                    continue;
+                }
+                let key = selectivity.key(&target_find);
+                if best_key.is_none_or(|best| (key, target) < best) {
+                    best_key = Some((key, target));
+                    best = Some((i, target, forward));
+                }
+            }
+        }
+        let Some((link, target, forward)) = best else {
+            return false;
+        };
+        let Step::Link {
+            ref deref,
+            atom1,
+            port1,
+            atom2,
+            port2,
+            ..
+        } = self.steps[link]
+        else {
+            unreachable!()
+        };
+        let (src, src_port, dst_port) = if forward {
+            (atom1, port1, port2)
+        } else {
+            (atom2, port2, port1)
+        };
+        let find = self.steps[target].find().unwrap();
+        let reached = Instr {
+            il: IL::Deref {
+                dst: find.dst,
+                src,
+                src_port,
+                dst_port,
+            },
+            span: deref.span,
+        };
+        let func = Instr {
+            il: IL::Func {
+                atom: find.dst,
+                functor: find.functor,
+            },
+            span: self.steps[target].instrs()[0].span,
+        };
+        self.block.extend([reached, func]);
+        self.placed[link] = true;
+        self.placed[target] = true;
+        true
+    }
+
+    /// Find the most selective atom that can be found.
+    fn anchor(&mut self, selectivity: &Selectivity) -> bool {
+        let anchor = (0..self.steps.len())
+            .filter(|&i| self.ready(i, None))
+            .filter_map(|i| Some((selectivity.key(&self.steps[i].find()?), i)))
+            .min();
+        match anchor {
+            Some((_, i)) => {
+                self.place(i);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The step finding the atom in `reg`, if it is found by a `find_atom`.
+    fn find_of(&self, reg: Reg) -> Option<usize> {
+        self.steps
+            .iter()
+            .position(|step| step.find().is_some_and(|find| find.dst == reg))
+    }
+}
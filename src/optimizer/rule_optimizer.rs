@@ -1,21 +1,67 @@
+//! Atom reuse: a rule that removes an atom and creates one of the same arity in the same membrane
+//! changes the functor of the removed atom instead, keeping the links that stay where they are.
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
 use super::Optimizer;
+use crate::{
+    codegen::{
+        il::{Instr, Reg, IL},
+        ILGenerator,
+    },
+    functor::{FunctorId, FunctorTable},
+};
 
+/// Reuses the atoms a rule removes for the atoms its body creates.
 #[derive(Debug, Default)]
-struct RuleOptimizer {
+pub struct RuleOptimizer {
     order: i32,
 }
 
 impl Optimizer for RuleOptimizer {
-    fn optimize(&self, il: &mut crate::codegen::ILGenerator) {
-        todo!()
+    fn optimize(&self, il: &mut ILGenerator) {
+        let (functors, rules) = il.rules_mut();
+        for rule in rules {
+            let mut next = rule.registers;
+            let mut changed = false;
+            // the first block matches the head, the others remove it and build the bodies
+            let Some((head, bodies)) = rule.blocks.split_first_mut() else {
+                continue;
+            };
+            for body in bodies {
+                if body
+                    .iter()
+                    .any(|instr| matches!(instr.il, IL::Branch { .. } | IL::Jump { .. }))
+                {
+                    continue;
+                }
+                let mut split = body.clone();
+                split_registers(&mut split, &mut next);
+                let mut reused = false;
+                while let Some(block) = reuse_one(head, &split, functors) {
+                    split = block;
+                    reused = true;
+                }
+                if reused {
+                    *body = split;
+                    changed = true;
+                }
+            }
+            if changed {
+                rule.allocate_registers();
+            }
+        }
     }
 
     fn pass(&self) -> u8 {
-        0
+        1
     }
 
     fn level(&self) -> u8 {
-        0
+        1
     }
 
     fn order(&self) -> i32 {
@@ -26,3 +72,225 @@ impl Optimizer for RuleOptimizer {
         self.order = order;
     }
 }
+
+/// Give each value written in a block that ends the rule a register of its own, numbered from
+/// `next`, so a reused atom can stay in its register for as long as it is needed.
+/// The registers are shared again once the atoms are reused.
+fn split_registers(block: &mut [Instr], next: &mut usize) {
+    let mut names = HashMap::new();
+    for instr in block {
+        let written = instr.il.writes();
+        instr
+            .il
+            .map_regs(&mut |reg| names.get(&reg).copied().unwrap_or(reg));
+        if let (Some(written), Some(dst)) = (written, instr.il.writes_mut()) {
+            *dst = Reg(*next);
+            *next += 1;
+            names.insert(written, *dst);
+        }
+    }
+}
+
+/// A removed atom to change into a created one.
+#[derive(Debug, Clone, Copy)]
+struct Reuse {
+    /// Index of the `RemoveAtom` in the block, and the register of the removed atom.
+    removed: usize,
+    atom: Reg,
+    /// Functor of the removed atom.
+    old: FunctorId,
+    /// Index of the `NewAtom` in the block, and the register and functor of the created atom.
+    created: usize,
+    dst: Reg,
+    functor: FunctorId,
+}
+
+/// Reuse one removed atom of `block` for an atom created after it,
+/// returning the rewritten block, or `None` if no atom can be reused.
+fn reuse_one(head: &[Instr], block: &[Instr], functors: &FunctorTable) -> Option<Vec<Instr>> {
+    for (created, instr) in block.iter().enumerate() {
+        let IL::NewAtom { dst, mem, functor } = instr.il else {
+            continue;
+        };
+        let arity = functors.get(functor).arity;
+        let mut candidates: Vec<Reuse> = block[..created]
+            .iter()
+            .enumerate()
+            .filter_map(|(removed, instr)| match instr.il {
+                IL::RemoveAtom { atom, mem: from } if from == mem => {
+                    let old = functor_of(head, &block[..removed], atom)?;
+                    (functors.get(old).arity == arity).then_some(Reuse {
+                        removed,
+                        atom,
+                        old,
+                        created,
+                        dst,
+                        functor,
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        // the atom that keeps the most links, then one that keeps its functor
+        candidates.sort_by_key(|c| (Reverse(c.kept_links(block)), c.old != c.functor, c.removed));
+        for candidate in candidates {
+            if let Some(reused) = candidate.apply(block) {
+                return Some(reused);
+            }
+        }
+    }
+    None
+}
+
+/// The functor of the atom in `reg` after `before`, which follows the head,
+/// if it is found, checked or created with a known functor.
+fn functor_of(head: &[Instr], before: &[Instr], reg: Reg) -> Option<FunctorId> {
+    let last = before
+        .iter()
+        .rev()
+        .chain(head.iter().rev())
+        .find(|instr| match instr.il {
+            IL::AlterFunctor { atom, .. } | IL::Func { atom, .. } => atom == reg,
+            ref il => il.writes() == Some(reg),
+        })?;
+    match last.il {
+        IL::FindAtom { functor, .. }
+        | IL::NewAtom { functor, .. }
+        | IL::AlterFunctor { functor, .. }
+        | IL::Func { functor, .. } => Some(functor),
+        _ => None,
+    }
+}
+
+impl Reuse {
+    /// The number of links the created atom takes over at the same port of the removed one.
+    fn kept_links(&self, block: &[Instr]) -> usize {
+        block[self.created + 1..]
+            .iter()
+            .filter(|instr| {
+                matches!(instr.il, IL::ReLink { atom1, port1, atom2, port2, .. }
+                    if atom1 == self.dst && atom2 == self.atom && port1 == port2)
+            })
+            .count()
+    }
+
+    /// Rewrite `block` to change the removed atom into the created one,
+    /// or `None` if a port of the removed atom would be read after it is linked anew.
+    fn apply(&self, block: &[Instr]) -> Option<Vec<Instr>> {
+        let Reuse {
+            removed,
+            atom,
+            created,
+            dst,
+            ..
+        } = *self;
+        let IL::RemoveAtom { mem, .. } = block[removed].il else {
+            unreachable!()
+        };
+        let first_write = |reg: Reg| {
+            block[created + 1..]
+                .iter()
+                .position(|instr| instr.il.writes() == Some(reg))
+                .map_or(block.len(), |i| created + 1 + i)
+        };
+        // the removed atom and the membrane are still in their registers
+        if block[removed + 1..created]
+            .iter()
+            .any(|instr| matches!(instr.il.writes(), Some(reg) if reg == atom || reg == mem))
+        {
+            return None;
+        }
+        // the created atom is held in `dst` until `dst_end` and is renamed to `atom` there,
+        // the removed atom is held in `atom` until `old_end`
+        let dst_end = first_write(dst);
+        let old_end = if dst == atom {
+            created
+        } else {
+            first_write(atom)
+        };
+        if dst != atom && old_end < dst_end {
+            return None;
+        }
+
+        // ports of the reused atom linked anew, which no longer lead where the removed atom's did
+        let mut linked = HashSet::new();
+        // relinks connecting a port to what it is already connected to
+        let mut kept = HashSet::new();
+        // up to the instruction writing `dst` again, which must not read the created atom from it
+        let end = (dst_end + 1).max(old_end).min(block.len());
+        for (k, instr) in block.iter().enumerate().take(end).skip(created + 1) {
+            let new = |reg: Reg| k < dst_end && reg == dst;
+            let old = |reg: Reg| k < old_end && reg == atom;
+            match instr.il {
+                IL::ReLink {
+                    atom1,
+                    port1,
+                    atom2,
+                    port2,
+                    ..
+                } => {
+                    if old(atom1) || (old(atom2) && linked.contains(&port2)) {
+                        return None;
+                    }
+                    if old(atom2) && new(atom1) && port1 == port2 {
+                        kept.insert(k);
+                    } else if new(atom1) {
+                        linked.insert(port1);
+                    }
+                }
+                IL::NewLink {
+                    atom1,
+                    port1,
+                    atom2,
+                    port2,
+                    ..
+                } => {
+                    if old(atom1) || old(atom2) {
+                        return None;
+                    }
+                    if new(atom1) {
+                        linked.insert(port1);
+                    }
+                    if new(atom2) {
+                        linked.insert(port2);
+                    }
+                }
+                ref il => {
+                    let mut reads = false;
+                    il.visit_reads(&mut |reg| reads |= old(reg) || (k == dst_end && reg == dst));
+                    if reads {
+                        return None;
+                    }
+                }
+            }
+        }
+
+        let mut reused = Vec::with_capacity(block.len());
+        for (k, instr) in block.iter().enumerate() {
+            if k == removed || kept.contains(&k) {
+                continue;
+            }
+            if k == created {
+                if self.functor != self.old {
+                    let alter = IL::AlterFunctor {
+                        atom,
+                        functor: self.functor,
+                    };
+                    reused.push(Instr {
+                        il: alter,
+                        span: instr.span,
+                    });
+                }
+                continue;
+            }
+            let mut instr = instr.clone();
+            if k > created && k < dst_end {
+                instr
+                    .il
+                    .map_regs(&mut |reg| if reg == dst { atom } else { reg });
+            }
+            reused.push(instr);
+        }
+        Some(reused)
+    }
+}
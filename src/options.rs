@@ -1,11 +1,59 @@
-use clap::{command, Parser};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use liblmntalc::{codegen::Target, parser::parse_define};
 
 #[derive(Parser, Debug)]
-#[command(author, version, about, long_about = None)]
-struct Args {
-    #[arg(short, long, default_value_t = 0)]
-    optimize_level: u8,
+#[command(author, version, about, long_about = None, args_conflicts_with_subcommands = true)]
+pub(crate) struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The LMNtal source file to compile.
+    #[arg(required = true)]
+    pub input: Option<PathBuf>,
+
+    /// Directories to search for included files and used modules.
+    #[arg(short = 'I', long = "include-path", value_name = "DIR", global = true)]
+    pub include_paths: Vec<PathBuf>,
+
+    /// Define a constant, written as `#NAME` in the program.
+    #[arg(short = 'D', long = "define", value_name = "NAME=VALUE", value_parser = parse_define, global = true)]
+    pub defines: Vec<(String, String)>,
+
+    /// Print the parsed program and the defined constants instead of the IL.
+    #[arg(long, global = true)]
+    pub dump_ast: bool,
+
+    /// Write the output to this file instead of stdout.
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+
+    /// Format of the generated IL, text if not given.
+    #[arg(long, value_enum, global = true)]
+    pub emit: Option<Target>,
+
+    /// Write the source line each instruction is generated from as a comment before it in the text IL.
+    #[arg(long, global = true)]
+    pub annotate: bool,
+
+    /// Optimization level, 0 if not given.
+    #[arg(short, long, global = true)]
+    pub optimize_level: Option<u8>,
+
+    /// Turn off the optimizer of this name, such as `PatternOptimizer`.
+    #[arg(short, long, value_name = "OPTIMIZER", global = true)]
+    pub disables: Vec<String>,
+
+    /// Atom names that may be used with several arities without a warning.
+    #[arg(long = "allow-overload", value_name = "NAME", global = true)]
+    pub overloads: Vec<String>,
+}
 
-    #[arg(short, long)]
-    disables: Vec<String>,
+#[derive(Subcommand, Debug)]
+pub(crate) enum Command {
+    /// Compile the project described by the `lmntal.toml` in the current directory.
+    ///
+    /// Options given on the command line override the values of the manifest.
+    Build,
 }
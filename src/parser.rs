@@ -2,8 +2,19 @@ pub mod data;
 pub mod rule_parser;
 
 use once_cell::sync::OnceCell;
-use pest::{Parser, Position};
-use std::collections::HashMap;
+use pest::Parser;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+};
+
+use crate::{
+    analysis,
+    diagnostic::{Diagnostic, Span},
+    functor::{FunctorId, FunctorTable},
+    source::{FileId, SourceMap},
+    stdlib,
+};
 
 use self::{data::*, rule_parser::parse_rule};
 
@@ -17,10 +28,12 @@ static mut LINK_ID: LinkId = 0;
 static mut RULE_ID: RuleId = 0;
 pub(crate) static mut ENTITY_ID: MembraneId = 0;
 
-pub static mut ATOMS: OnceCell<HashMap<AtomId, Atom>> = OnceCell::new();
-pub static mut LINKS: OnceCell<HashMap<LinkId, Link>> = OnceCell::new();
-pub static mut RULES: OnceCell<HashMap<RuleId, rule_parser::Rule>> = OnceCell::new();
-pub static mut MEMS: OnceCell<HashMap<MembraneId, Membrane>> = OnceCell::new();
+pub static mut ATOMS: OnceCell<BTreeMap<AtomId, Atom>> = OnceCell::new();
+pub static mut LINKS: OnceCell<BTreeMap<LinkId, Link>> = OnceCell::new();
+pub static mut RULES: OnceCell<BTreeMap<RuleId, rule_parser::Rule>> = OnceCell::new();
+pub static mut MEMS: OnceCell<BTreeMap<MembraneId, Membrane>> = OnceCell::new();
+/// Functors of the atoms of the initial process and of the rules.
+pub static mut FUNCTORS: OnceCell<FunctorTable> = OnceCell::new();
 
 #[derive(Debug, Clone, Copy)]
 pub struct Context {
@@ -32,9 +45,190 @@ pub struct Context {
     membrane: MembraneId,
 }
 
-pub fn parse_lmntal(file: &str) -> Result<Symbol, Box<pest::error::Error<Rule>>> {
-    let pairs = LMNParser::parse(Rule::Program, file)?;
-    let mut init_process = Vec::new();
+/// Options for loading the files of a program.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Directories searched for included files and used modules,
+    /// after the directory of the file that includes them.
+    pub include_paths: Vec<PathBuf>,
+    /// Values of the constants written as `#NAME`, by name.
+    pub constants: BTreeMap<String, String>,
+}
+
+/// Parse a constant definition `NAME=VALUE` given with `-D`.
+///
+/// The value must be something an atom can be named, such as `100` or `fast`.
+pub fn parse_define(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `NAME=VALUE`, found `{}`", s))?;
+    let mut chars = name.chars();
+    if !chars.next().is_some_and(|c| c.is_ascii_uppercase())
+        || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!(
+            "`{}` is not a constant name, which starts with an uppercase letter",
+            name
+        ));
+    }
+    match LMNParser::parse(Rule::AtomName, value) {
+        Ok(pairs) if pairs.as_str() == value => Ok((name.to_string(), value.to_string())),
+        _ => Err(format!(
+            "`{}` is not a value of a constant, which is a number or an atom name",
+            value
+        )),
+    }
+}
+
+/// The value of a constant `#NAME`.
+///
+/// An undefined constant is reported and gives `None`.
+/// Parse an integer literal, decimal or hexadecimal after `0x`, written at `span`.
+pub(crate) fn parse_int(text: &str, span: Span) -> Result<i64, Diagnostic> {
+    let value = match text.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    value.map_err(|_| {
+        Diagnostic::error(format!("integer `{}` does not fit in 64 bits", text))
+            .with_label(span, "out of range")
+    })
+}
+
+/// Parse a float literal written at `span`.
+pub(crate) fn parse_float(text: &str, span: Span) -> Result<f64, Diagnostic> {
+    match text.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(
+            Diagnostic::error(format!("float `{}` is out of range", text))
+                .with_label(span, "out of range"),
+        ),
+    }
+}
+
+/// The name of the atom written as `text` at `span`. An integer is named in decimal, which is
+/// how the runtime reads the value of an integer atom.
+pub(crate) fn atom_name(text: &str, span: Span) -> Result<String, Diagnostic> {
+    if !text.starts_with(|c: char| c.is_ascii_digit()) {
+        Ok(text.to_string())
+    } else if text.contains('.') {
+        parse_float(text, span).map(|_| text.to_string())
+    } else {
+        parse_int(text, span).map(|value| value.to_string())
+    }
+}
+
+pub(crate) fn constant(pair: &pest::iterators::Pair<Rule>) -> Option<String> {
+    let name = &pair.as_str()[1..];
+    let loader = loader();
+    if let Some(value) = loader.options.constants.get(name) {
+        return Some(value.clone());
+    }
+    let mut d = Diagnostic::error(format!("constant `#{}` is not defined", name))
+        .with_label(span(pair.as_span()), "not defined");
+    let names = loader.options.constants.keys().map(|n| n.as_str());
+    d = match crate::util::closest_name(name, names) {
+        Some(similar) => d.with_help(format!("did you mean `#{}`?", similar)),
+        None => d.with_help(format!("define it with `-D {}=...`", name)),
+    };
+    loader.diagnostics.push(d);
+    None
+}
+
+/// State of loading the files of a program, kept like the tables above.
+struct Loader {
+    sources: SourceMap,
+    options: ParseOptions,
+    /// Files being parsed, innermost last, with the directive that loaded each of them.
+    stack: Vec<(FileId, Option<Span>)>,
+    /// Files by their canonical path.
+    files: HashMap<PathBuf, FileId>,
+    /// Modules already used by each membrane.
+    used: HashSet<(MembraneId, FileId)>,
+    /// The module each file declares.
+    modules: HashMap<FileId, (String, Span)>,
+    /// The module whose rules are being parsed, while a used module is loaded.
+    module: Option<String>,
+    /// Links of the initial process waiting for their other end, by name.
+    open_links: HashMap<String, LinkId>,
+    /// Atoms written as arguments of other atoms, which are in a membrane without being
+    /// one of the processes listed in it, until the declaration list they are in is parsed.
+    nested_atoms: Vec<AtomId>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+static mut LOADER: Option<Loader> = None;
+/// Offset of the file being parsed in the source map.
+static mut FILE_START: usize = 0;
+
+/// The id of the functor `name/arity` in [`FUNCTORS`].
+pub(crate) fn intern(name: &str, arity: usize) -> FunctorId {
+    unsafe {
+        FUNCTORS.get_or_init(FunctorTable::default);
+        FUNCTORS.get_mut().unwrap().intern(name, arity)
+    }
+}
+
+/// Convert a span of the file being parsed into a span of the source map.
+pub(crate) fn span(span: pest::Span) -> Span {
+    Span::new(offset(span.start()), offset(span.end()))
+}
+
+/// Convert an offset in the file being parsed into an offset in the source map.
+pub(crate) fn offset(pos: usize) -> usize {
+    unsafe { FILE_START + pos }
+}
+
+/// Parse the program in `file`, loading the files it includes and the modules it uses
+/// into `sources`.
+///
+/// The links of the initial process, the types of the guards and the links written once in the
+/// heads are checked as well, so the returned program can be passed to the code generator. The analyses that only warn are run
+/// by [`analysis::analyze`].
+pub fn parse_lmntal(
+    sources: &mut SourceMap,
+    file: FileId,
+    options: &ParseOptions,
+) -> Result<Symbol, Vec<Diagnostic>> {
+    let mut files = HashMap::new();
+    files.insert(canonical(&sources.get(file).path), file);
+    unsafe {
+        // every program is numbered from zero, so the same program always gets the same ids
+        LINK_ID = 0;
+        RULE_ID = 0;
+        ENTITY_ID = 0;
+        ATOMS.take();
+        LINKS.take();
+        RULES.take();
+        MEMS.take();
+        FUNCTORS.take();
+        LOADER = Some(Loader {
+            sources: std::mem::take(sources),
+            options: options.clone(),
+            stack: vec![],
+            files,
+            used: HashSet::new(),
+            modules: HashMap::new(),
+            module: None,
+            open_links: HashMap::new(),
+            nested_atoms: vec![],
+            diagnostics: vec![],
+        });
+    }
+    let result = parse_program(file);
+    let loader = unsafe { LOADER.take().unwrap() };
+    *sources = loader.sources;
+    match result {
+        Ok(_) if !loader.diagnostics.is_empty() => Err(loader.diagnostics),
+        Err(mut diagnostics) => {
+            diagnostics.splice(0..0, loader.diagnostics);
+            Err(diagnostics)
+        }
+        ok => ok,
+    }
+}
+
+fn parse_program(file: FileId) -> Result<Symbol, Vec<Diagnostic>> {
     let id = unsafe { ENTITY_ID };
     unsafe { ENTITY_ID += 1 };
     let ctx = Context {
@@ -42,40 +236,9 @@ pub fn parse_lmntal(file: &str) -> Result<Symbol, Box<pest::error::Error<Rule>>>
         pos: None,
         membrane: id,
     };
-    for pair in pairs {
-        match pair.as_rule() {
-            Rule::Program => {
-                for pair in pair.into_inner() {
-                    match pair.as_rule() {
-                        Rule::WorldProcessList => {
-                            init_process.append(&mut parse_world_process_list(pair, ctx));
-                        }
-                        Rule::EOI => {}
-                        _ => {
-                            unreachable!("Unexpected rule: {:?}", pair.as_rule());
-                        }
-                    }
-                }
-            }
-            _ => {
-                unreachable!("Unexpected rule: {:?}", pair.as_rule());
-            }
-        }
-    }
-
-    unsafe {
-        let links = LINKS.get_or_init(HashMap::new);
-        for link in links.values() {
-            if link.link2.is_none() {
-                return Err(Box::new(pest::error::Error::new_from_pos(
-                    pest::error::ErrorVariant::CustomError {
-                        message: "Free link is not allowed here.".to_string(),
-                    },
-                    Position::new(file, link.pos1.unwrap()).unwrap(),
-                )));
-            }
-        }
-    }
+    let mut init_process = load_file(file, ctx, None, None);
+    let source = loader().sources.get(file);
+    let file_span = Span::new(source.start, source.start + source.text.len());
 
     let mut rule_set = vec![];
     for symbol in init_process.iter() {
@@ -86,7 +249,7 @@ pub fn parse_lmntal(file: &str) -> Result<Symbol, Box<pest::error::Error<Rule>>>
     init_process.retain(|symbol| !matches!(symbol, Symbol::Rule(_)));
 
     unsafe {
-        MEMS.get_or_init(HashMap::new);
+        MEMS.get_or_init(BTreeMap::new);
         MEMS.get_mut().unwrap().insert(
             id,
             Membrane {
@@ -95,30 +258,300 @@ pub fn parse_lmntal(file: &str) -> Result<Symbol, Box<pest::error::Error<Rule>>>
                 name: "init".to_string(),
                 process: init_process,
                 rule_set,
+                span: file_span,
             },
         );
     }
+    let diagnostics: Vec<Diagnostic> = unsafe {
+        let rules = RULES.get_or_init(BTreeMap::new);
+        rules
+            .values()
+            .flat_map(|rule| rule.diagnostics.iter().cloned())
+            .collect()
+    };
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+    // code generation relies on connected links and on the types inferred for the guards,
+    // so these are checked for every program parsed, not only by the command line
+    let mut diagnostics = analysis::link::check_links(id);
+    diagnostics.append(&mut analysis::types::check_types(id));
+    diagnostics.append(&mut analysis::link::check_rule_links(id));
+    if !diagnostics.is_empty() {
+        diagnostics.sort_by_key(|d| d.labels.first().map(|l| l.span));
+        return Err(diagnostics);
+    }
     Ok(Symbol::Membrane(id))
 }
 
+fn loader() -> &'static mut Loader {
+    unsafe { LOADER.as_mut().unwrap() }
+}
+
+fn canonical(path: &std::path::Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Parse a source file into the membrane of `ctx`.
+///
+/// `directive` is where the file is included, and `module` the name it is used by, if any.
+/// A used module may only contain rules.
+fn load_file(
+    file: FileId,
+    ctx: Context,
+    directive: Option<Span>,
+    module: Option<&str>,
+) -> Vec<Symbol> {
+    let loader = loader();
+    let source = loader.sources.get(file);
+    let (text, start) = (source.text.clone(), source.start);
+    let pairs = match LMNParser::parse(Rule::Program, &text) {
+        Ok(pairs) => pairs,
+        Err(e) => {
+            let mut d: Diagnostic = e.into();
+            for label in &mut d.labels {
+                label.span = Span::new(start + label.span.start, start + label.span.end);
+            }
+            loader.diagnostics.push(d);
+            return vec![];
+        }
+    };
+
+    let outer = unsafe { std::mem::replace(&mut FILE_START, start) };
+    loader.stack.push((file, directive));
+    let pair = pairs
+        .flatten()
+        .find(|p| p.as_rule() == Rule::WorldProcessList)
+        .unwrap();
+    let processes = pair
+        .clone()
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::DeclarationList);
+    let list = match (module, processes) {
+        (Some(name), Some(processes)) => {
+            loader.diagnostics.push(
+                Diagnostic::error(format!("module `{}` contains processes", name))
+                    .with_label(span(processes.as_span()), "not a rule")
+                    .with_note("a used module may only contain rules")
+                    .with_help("use `include` to load processes from another file"),
+            );
+            // still check the name of the module
+            for decl in pair.into_inner() {
+                if decl.as_rule() == Rule::ModuleDecl {
+                    parse_module(decl);
+                }
+            }
+            vec![]
+        }
+        (Some(name), None) => {
+            let outer_module = loader.module.replace(name.to_string());
+            let list = parse_world_process_list(pair, ctx);
+            loader.module = outer_module;
+            list
+        }
+        _ => parse_world_process_list(pair, ctx),
+    };
+    loader.stack.pop();
+    unsafe { FILE_START = outer };
+    list
+}
+
+/// Find the file a directive refers to, in the directory of the including file
+/// or in the include paths.
+///
+/// `what` describes the file for messages, and `verb` how the directive loads it.
+/// `stdlib` is the name of a module of the standard library to fall back to.
+fn resolve_file(
+    name: &str,
+    what: &str,
+    verb: &str,
+    directive: Span,
+    stdlib: Option<&str>,
+) -> Option<FileId> {
+    let loader = loader();
+    let (current, _) = *loader.stack.last().unwrap();
+    let including = loader.sources.get(current).path.clone();
+    let mut dirs = vec![including
+        .parent()
+        .map(|p| p.to_path_buf())
+        .unwrap_or_default()];
+    dirs.extend(loader.options.include_paths.iter().cloned());
+
+    let Some(path) = dirs.iter().map(|dir| dir.join(name)).find(|p| p.is_file()) else {
+        if let Some(module) = stdlib {
+            if let Some(text) = stdlib::module(module) {
+                let path = stdlib::path(module);
+                let file = *loader
+                    .files
+                    .entry(path.clone())
+                    .or_insert_with(|| loader.sources.add(path, text.to_string()));
+                return Some(file);
+            }
+        }
+        let searched: Vec<String> = dirs
+            .iter()
+            .map(|d| match d.as_os_str().is_empty() {
+                true => ".".to_string(),
+                false => d.display().to_string(),
+            })
+            .collect();
+        let mut d = Diagnostic::error(format!("cannot find {}", what))
+            .with_label(
+                directive,
+                format!("{} from `{}`", verb, including.display()),
+            )
+            .with_note(format!("looked for `{}` in: {}", name, searched.join(", ")));
+        if stdlib.is_some() {
+            let modules: Vec<String> = stdlib::MODULES
+                .iter()
+                .map(|(name, _)| format!("`{}`", name))
+                .collect();
+            d = d.with_note(format!(
+                "the standard library has the modules {}",
+                modules.join(", ")
+            ));
+        }
+        loader
+            .diagnostics
+            .push(d.with_help("add the directory that contains it with `-I`"));
+        return None;
+    };
+
+    let key = canonical(&path);
+    if let Some(file) = loader.files.get(&key) {
+        return Some(*file);
+    }
+    match std::fs::read_to_string(&path) {
+        Ok(text) => {
+            let file = loader.sources.add(path, text);
+            loader.files.insert(key, file);
+            Some(file)
+        }
+        Err(e) => {
+            loader.diagnostics.push(
+                Diagnostic::error(format!("cannot read `{}`: {}", path.display(), e)).with_label(
+                    directive,
+                    format!("{} from `{}`", verb, including.display()),
+                ),
+            );
+            None
+        }
+    }
+}
+
+/// Report a file that is already being loaded, which would load itself forever.
+fn check_cycle(file: FileId, directive: Span) -> bool {
+    let loader = loader();
+    let Some(first) = loader.stack.iter().position(|(f, _)| *f == file) else {
+        return true;
+    };
+    let path = |f: FileId| loader.sources.get(f).path.display().to_string();
+    let mut d = Diagnostic::error(format!("`{}` is included recursively", path(file)))
+        .with_label(directive, format!("this includes `{}` again", path(file)));
+    let chain = &loader.stack[first..];
+    for (i, (f, _)) in chain.iter().enumerate() {
+        let next = chain.get(i + 1).map_or(file, |(f, _)| *f);
+        d = d.with_note(format!("`{}` includes `{}`", path(*f), path(next)));
+    }
+    loader.diagnostics.push(d);
+    false
+}
+
+fn parse_include(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Vec<Symbol> {
+    let directive = span(pair.as_span());
+    let name = pair.into_inner().next().unwrap().as_str();
+    match resolve_file(
+        name,
+        &format!("file `{}`", name),
+        "included",
+        directive,
+        None,
+    ) {
+        Some(file) if check_cycle(file, directive) => load_file(file, ctx, Some(directive), None),
+        _ => vec![],
+    }
+}
+
+fn parse_use(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Vec<Symbol> {
+    let directive = span(pair.as_span());
+    let name = pair.into_inner().next().unwrap().as_str();
+    let file = resolve_file(
+        &format!("{}.lmn", name),
+        &format!("module `{}`", name),
+        "used",
+        directive,
+        Some(name),
+    );
+    let Some(file) = file else {
+        return vec![];
+    };
+    // using a module twice in a membrane does not load its rules twice
+    if !loader().used.insert((ctx.membrane, file)) || !check_cycle(file, directive) {
+        return vec![];
+    }
+    let rules = load_file(file, ctx, Some(directive), Some(name));
+
+    let loader = loader();
+    let path = loader.sources.get(file).path.display().to_string();
+    match loader.modules.get(&file) {
+        Some((declared, _)) if declared == name => {}
+        Some((declared, decl)) => loader.diagnostics.push(
+            Diagnostic::error(format!(
+                "`{}` declares module `{}`, but is used as `{}`",
+                path, declared, name
+            ))
+            .with_label(directive, format!("used as `{}` here", name))
+            .with_label(*decl, format!("declared as `{}` here", declared)),
+        ),
+        None => loader.diagnostics.push(
+            Diagnostic::error(format!("`{}` is not a module", path))
+                .with_label(directive, "used as a module here")
+                .with_help(format!("add `module({})` to `{}`", name, path)),
+        ),
+    }
+    rules
+}
+
+fn parse_module(pair: pest::iterators::Pair<Rule>) {
+    let directive = span(pair.as_span());
+    let name = pair.into_inner().next().unwrap().as_str().to_string();
+    let loader = loader();
+    let (file, _) = *loader.stack.last().unwrap();
+    if let Some((declared, decl)) = loader.modules.get(&file) {
+        // the declaration is seen again when another membrane uses the module
+        if *decl == directive {
+            return;
+        }
+        loader.diagnostics.push(
+            Diagnostic::error(format!(
+                "`{}` already declares module `{}`",
+                loader.sources.get(file).path.display(),
+                declared
+            ))
+            .with_label(directive, "second declaration")
+            .with_label(*decl, "first declared here"),
+        );
+        return;
+    }
+    loader.modules.insert(file, (name, directive));
+}
+
 fn parse_world_process_list(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Vec<Symbol> {
     let mut list: Vec<Symbol> = Vec::new();
     for pair in pair.into_inner() {
         match pair.as_rule() {
             Rule::Rule => {
-                list.push(parse_rule(pair, ctx));
+                list.push(parse_rule(pair));
             }
+            Rule::ModuleDecl => parse_module(pair),
+            Rule::UseDecl => list.append(&mut parse_use(pair, ctx)),
+            Rule::IncludeDecl => list.append(&mut parse_include(pair, ctx)),
             Rule::DeclarationList => {
+                // the atoms nested in this list, those of inner membranes are taken by them
+                let first = loader().nested_atoms.len();
                 list.append(&mut parse_declaration_list(pair, ctx));
-
-                unsafe {
-                    let atoms = ATOMS.get().unwrap();
-                    for (id, atom) in atoms.iter() {
-                        if atom.membrane == ctx.membrane && !list.contains(&Symbol::Atom(*id)) {
-                            list.push(Symbol::Atom(*id));
-                        }
-                    }
-                }
+                let nested = loader().nested_atoms.split_off(first);
+                list.extend(nested.into_iter().map(Symbol::Atom));
             }
             Rule::EOI => {}
             _ => {
@@ -162,48 +595,36 @@ fn parse_declaration_list(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Ve
 }
 
 fn parse_declaration(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
-    for pair in pair.into_inner() {
-        match pair.as_rule() {
-            Rule::UnitAtom => {
-                return parse_unit_atom(pair, ctx);
-            }
-            Rule::Context => {
-                panic!(
-                    "{:?}, Context can only be declared in rule",
-                    pair.line_col()
-                );
-            }
-            _ => {
-                unreachable!("Unexpected rule: {:?}", pair.as_rule());
-            }
+    let pair = pair.into_inner().next().unwrap();
+    match pair.as_rule() {
+        Rule::UnitAtom => parse_unit_atom(pair, ctx),
+        Rule::Context => {
+            panic!(
+                "{:?}, Context can only be declared in rule",
+                pair.line_col()
+            );
+        }
+        _ => {
+            unreachable!("Unexpected rule: {:?}", pair.as_rule());
         }
     }
-    unreachable!();
 }
 
 fn parse_unit_atom(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
-    for pair in pair.into_inner() {
-        match pair.as_rule() {
-            Rule::Atom => {
-                return parse_atom(pair, ctx);
-            }
-            Rule::Membrane => {
-                return parse_membrane(pair, ctx);
-            }
-            Rule::Link => {
-                return parse_link(pair, ctx);
-            }
-            _ => {
-                unreachable!();
-            }
+    let pair = pair.into_inner().next().unwrap();
+    match pair.as_rule() {
+        Rule::Atom => parse_atom(pair, ctx),
+        Rule::Membrane => parse_membrane(pair, ctx),
+        Rule::Link => parse_link(pair, ctx),
+        _ => {
+            unreachable!();
         }
     }
-    unreachable!();
 }
 
 fn parse_link(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
     let mut name = "".to_string();
-    let pos = pair.as_span().start_pos().pos();
+    let link_span = span(pair.as_span());
     for pair in pair.into_inner() {
         match pair.as_rule() {
             Rule::LinkName => {
@@ -216,30 +637,33 @@ fn parse_link(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
     }
 
     unsafe {
-        LINKS.get_or_init(HashMap::new);
-        // find if there is a link with the same name
-        for (k, v) in LINKS.get_mut().unwrap().iter_mut() {
-            if v.name == name {
-                v.link2 = Some((ctx.from, ctx.pos.unwrap()));
-                v.pos2 = Some(pos);
-                return Symbol::Link(*k);
-            }
+        LINKS.get_or_init(BTreeMap::new);
+        let links = LINKS.get_mut().unwrap();
+        // connect a link with the same name waiting for its other end,
+        // a name used more than twice starts a new link and is reported by the analysis
+        if let Some(id) = loader().open_links.remove(&name) {
+            let link = links.get_mut(&id).unwrap();
+            link.link2 = Some((ctx.from, ctx.pos.unwrap()));
+            link.span2 = Some(link_span);
+            return Symbol::Link(id);
         }
         let id = LINK_ID;
         LINK_ID += 1;
+        loader().open_links.insert(name.clone(), id);
         let link = Link {
             name,
             link1: Some((ctx.from, ctx.pos.unwrap())),
             link2: None,
-            pos1: Some(pos),
-            pos2: None,
+            span1: Some(link_span),
+            span2: None,
         };
-        LINKS.get_mut().unwrap().insert(id, link);
+        links.insert(id, link);
         Symbol::Link(id)
     }
 }
 
 fn parse_membrane(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
+    let mem_span = span(pair.as_span());
     let mut name = "".to_string();
     let mut process: Vec<Symbol> = Vec::new();
     let parent = ctx.membrane;
@@ -255,6 +679,9 @@ fn parse_membrane(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
             Rule::AtomName => {
                 name = pair.as_str().to_string();
             }
+            Rule::Constant => {
+                name = constant(&pair).unwrap_or_default();
+            }
             Rule::WorldProcessList => {
                 process.append(&mut parse_world_process_list(pair, ctx));
             }
@@ -278,10 +705,11 @@ fn parse_membrane(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
         name,
         process,
         rule_set,
+        span: mem_span,
     };
 
     unsafe {
-        MEMS.get_or_init(HashMap::new);
+        MEMS.get_or_init(BTreeMap::new);
         MEMS.get_mut().unwrap().insert(id, membrane);
     }
     Symbol::Membrane(id)
@@ -289,14 +717,23 @@ fn parse_membrane(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
 
 fn parse_atom(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
     let mut name: String = "".to_string();
+    let mut span = Span::default();
     let mut process: Vec<Symbol> = Vec::new();
     let id = unsafe { ENTITY_ID };
     unsafe { ENTITY_ID += 1 };
     let mut pos = 0;
     for pair in pair.into_inner() {
         match pair.as_rule() {
-            Rule::AtomName => {
-                name = pair.as_str().to_string();
+            Rule::AtomName | Rule::Constant => {
+                span = self::span(pair.as_span());
+                let text = match pair.as_rule() {
+                    Rule::Constant => constant(&pair).unwrap_or_else(|| pair.as_str().to_string()),
+                    _ => pair.as_str().to_string(),
+                };
+                name = atom_name(&text, span).unwrap_or_else(|d| {
+                    loader().diagnostics.push(d);
+                    text
+                });
             }
             Rule::DeclarationList => {
                 process.append(&mut parse_declaration_list(
@@ -317,11 +754,14 @@ fn parse_atom(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
         }
     }
 
+    // an atom written as an argument has one more link, to its parent
+    let arity = process.len() + matches!(ctx.from, Symbol::Atom(_)) as usize;
     let mut atom = Atom {
         membrane: ctx.membrane,
         id,
-        name,
+        functor: intern(&name, arity),
         links: process,
+        span,
     };
 
     let res = match ctx.from {
@@ -330,16 +770,17 @@ fn parse_atom(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
                 name: String::new(),
                 link1: Some((Symbol::Atom(from_id), ctx.pos.unwrap())),
                 link2: Some((Symbol::Atom(id), atom.links.len())),
-                pos1: None,
-                pos2: None,
+                span1: None,
+                span2: None,
             };
             unsafe {
                 let id = LINK_ID;
                 LINK_ID += 1;
 
-                LINKS.get_or_init(HashMap::new);
+                LINKS.get_or_init(BTreeMap::new);
                 LINKS.get_mut().unwrap().insert(id, link);
-                atom.links = vec![Symbol::Link(id)];
+                atom.links.push(Symbol::Link(id));
+                loader().nested_atoms.push(atom.id);
                 Symbol::Link(id)
             }
         }
@@ -347,7 +788,7 @@ fn parse_atom(pair: pest::iterators::Pair<Rule>, ctx: Context) -> Symbol {
     };
 
     unsafe {
-        _ = ATOMS.get_or_init(HashMap::new);
+        _ = ATOMS.get_or_init(BTreeMap::new);
         ATOMS.get_mut().unwrap().insert(id, atom);
     }
 
@@ -1,3 +1,5 @@
+use crate::{diagnostic::Span, functor::FunctorId};
+
 pub type AtomId = usize;
 pub type LinkId = usize;
 pub type RuleId = usize;
@@ -54,8 +56,11 @@ impl Symbol {
 pub struct Atom {
     pub membrane: MembraneId,
     pub id: AtomId,
-    pub name: String,
+    /// The name and arity of this atom in the [`FUNCTORS`](crate::parser::FUNCTORS) table.
+    pub functor: FunctorId,
     pub links: Vec<Symbol>,
+    /// Where the name of this atom is written in the source.
+    pub span: Span,
 }
 
 #[derive(Debug)]
@@ -63,8 +68,10 @@ pub struct Link {
     pub name: String,
     pub link1: Option<(Symbol, usize)>,
     pub link2: Option<(Symbol, usize)>,
-    pub pos1: Option<usize>,
-    pub pos2: Option<usize>,
+    /// Where each end of this link is written,
+    /// the link between an atom and an atom written as its argument has none.
+    pub span1: Option<Span>,
+    pub span2: Option<Span>,
 }
 
 #[derive(Debug, Default)]
@@ -74,4 +81,7 @@ pub struct Membrane {
     pub name: String,
     pub process: Vec<Symbol>,
     pub rule_set: Vec<RuleId>,
+    /// Where this membrane is written, the whole file for the root membrane
+    /// and the whole head or body for the membrane of a rule.
+    pub span: Span,
 }
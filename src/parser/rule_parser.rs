@@ -1,13 +1,20 @@
+use std::fmt::Display;
+
+use crate::util::closest_name;
+
 use super::*;
 
-pub fn parse_rule(pair: pest::iterators::Pair<ParseRule>, ctx: Context) -> Symbol {
+pub fn parse_rule(pair: pest::iterators::Pair<ParseRule>) -> Symbol {
+    // taken before parsing, the rules written in the body of this one are numbered after it
     let id = unsafe { RULE_ID };
+    unsafe { RULE_ID += 1 };
     let mut rule = Rule::new(pair.line_col());
-    rule.parse(pair, ctx);
+    rule.span = span(pair.as_span());
+    rule.module = loader().module.clone();
+    rule.parse(pair);
 
     unsafe {
-        RULE_ID += 1;
-        RULES.get_or_init(HashMap::new);
+        RULES.get_or_init(BTreeMap::new);
         RULES.get_mut().unwrap().insert(id, rule);
     }
 
@@ -16,7 +23,13 @@ pub fn parse_rule(pair: pest::iterators::Pair<ParseRule>, ctx: Context) -> Symbo
 
 // Data structures for rules.
 
-#[derive(Debug, Clone, Copy)]
+/// The membrane of the processes written directly in a rule, the one the rule is applied in.
+///
+/// The atoms and membranes of a rule are numbered from 0 in the rule, so this id is never given
+/// to one of them.
+pub const RULE_MEMBRANE: MembraneId = MembraneId::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum GuardOperator {
     Add,
     Sub,
@@ -31,9 +44,19 @@ pub enum GuardOperator {
     Ge,
     And,
     Or,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FEq,
+    FNeq,
+    FLt,
+    FLe,
+    FGt,
+    FGe,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Type {
     Int,
     Float,
@@ -43,27 +66,282 @@ pub enum Type {
     Ground,
 }
 
+impl GuardOperator {
+    pub fn is_arithmetic(&self) -> bool {
+        matches!(
+            self.base(),
+            GuardOperator::Add
+                | GuardOperator::Sub
+                | GuardOperator::Mul
+                | GuardOperator::Div
+                | GuardOperator::Mod
+        )
+    }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(
+            self.base(),
+            GuardOperator::Eq
+                | GuardOperator::Neq
+                | GuardOperator::Lt
+                | GuardOperator::Le
+                | GuardOperator::Gt
+                | GuardOperator::Ge
+        )
+    }
+
+    /// Whether this is one of the operators that only accept floats, such as `+.`.
+    pub fn is_float(&self) -> bool {
+        self.base() != *self
+    }
+
+    /// The operator without its float-only restriction, `+.` becomes `+`.
+    pub fn base(&self) -> GuardOperator {
+        match self {
+            GuardOperator::FAdd => GuardOperator::Add,
+            GuardOperator::FSub => GuardOperator::Sub,
+            GuardOperator::FMul => GuardOperator::Mul,
+            GuardOperator::FDiv => GuardOperator::Div,
+            GuardOperator::FEq => GuardOperator::Eq,
+            GuardOperator::FNeq => GuardOperator::Neq,
+            GuardOperator::FLt => GuardOperator::Lt,
+            GuardOperator::FLe => GuardOperator::Le,
+            GuardOperator::FGt => GuardOperator::Gt,
+            GuardOperator::FGe => GuardOperator::Ge,
+            op => *op,
+        }
+    }
+
+    /// The comparison that holds exactly when this one does not, `<` becomes `>=`.
+    pub fn negate(&self) -> GuardOperator {
+        match self {
+            GuardOperator::Eq => GuardOperator::Neq,
+            GuardOperator::Neq => GuardOperator::Eq,
+            GuardOperator::Lt => GuardOperator::Ge,
+            GuardOperator::Le => GuardOperator::Gt,
+            GuardOperator::Gt => GuardOperator::Le,
+            GuardOperator::Ge => GuardOperator::Lt,
+            GuardOperator::FEq => GuardOperator::FNeq,
+            GuardOperator::FNeq => GuardOperator::FEq,
+            GuardOperator::FLt => GuardOperator::FGe,
+            GuardOperator::FLe => GuardOperator::FGt,
+            GuardOperator::FGt => GuardOperator::FLe,
+            GuardOperator::FGe => GuardOperator::FLt,
+            op => unreachable!("`{}` is not a comparison", op),
+        }
+    }
+}
+
+/// Functions that can be called in guard expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GuardFunction {
+    /// Conversion to int, truncating floats.
+    Int,
+    /// Conversion to float.
+    Float,
+    Abs,
+    Min,
+    Max,
+    Sqrt,
+}
+
+impl Display for GuardFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            GuardFunction::Int => "int",
+            GuardFunction::Float => "float",
+            GuardFunction::Abs => "abs",
+            GuardFunction::Min => "min",
+            GuardFunction::Max => "max",
+            GuardFunction::Sqrt => "sqrt",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Display for GuardOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            GuardOperator::Add => "+",
+            GuardOperator::Sub => "-",
+            GuardOperator::Mul => "*",
+            GuardOperator::Div => "/",
+            GuardOperator::Mod => "%",
+            GuardOperator::Eq => "==",
+            GuardOperator::Neq => "!=",
+            GuardOperator::Lt => "<",
+            GuardOperator::Le => "<=",
+            GuardOperator::Gt => ">",
+            GuardOperator::Ge => ">=",
+            GuardOperator::And => "&&",
+            GuardOperator::Or => "||",
+            GuardOperator::FAdd => "+.",
+            GuardOperator::FSub => "-.",
+            GuardOperator::FMul => "*.",
+            GuardOperator::FDiv => "/.",
+            GuardOperator::FEq => "==.",
+            GuardOperator::FNeq => "!=.",
+            GuardOperator::FLt => "<.",
+            GuardOperator::FLe => "<=.",
+            GuardOperator::FGt => ">.",
+            GuardOperator::FGe => ">=.",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Type::Int => "int",
+            Type::Float => "float",
+            Type::String => "string",
+            Type::Unary => "unary",
+            Type::Uniq => "uniq",
+            Type::Ground => "ground",
+        };
+        write!(f, "{}", s)
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum GuardNode {
     Value(Symbol),
-    TypeConstraint(Type, Vec<Symbol>),
+    TypeConstraint(Type, Vec<Symbol>, Span),
+    /// A temporary variable of the case, as an index into [`Case::vars`].
+    Var(usize),
     IntValue(i64),
     FloatValue(f64),
-    Operation(GuardOperator, Box<GuardNode>, Box<GuardNode>),
+    Operation(GuardOperator, Box<GuardNode>, Box<GuardNode>, Span),
+    Call(GuardFunction, Vec<GuardNode>, Span),
+    /// A predicate on a membrane of the head.
+    MemPredicate(MemPredicate, MembraneId, Span),
+    /// Logical negation of a condition.
+    Not(Box<GuardNode>, Span),
+}
+
+/// Predicates on membranes matched by the head of a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemPredicate {
+    /// The membrane contains exactly this many atoms.
+    NAtoms(usize),
+    /// The membrane contains exactly this many child membranes.
+    NMems(usize),
+    /// The membrane has this name.
+    Name(String),
+    /// The membrane contains no rules.
+    NoRules,
+}
+
+impl Display for MemPredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemPredicate::NAtoms(count) => write!(f, "natoms({})", count),
+            MemPredicate::NMems(count) => write!(f, "nmems({})", count),
+            MemPredicate::Name(name) => write!(f, "name({})", name),
+            MemPredicate::NoRules => write!(f, "norules"),
+        }
+    }
+}
+
+impl GuardNode {
+    /// Rewrite this guard into disjunctive normal form.
+    ///
+    /// Each returned clause is a list of conditions joined by `&&`,
+    /// the guard holds if any of the clauses holds.
+    /// Negations are pushed down to the conditions: negated comparisons are flipped,
+    /// so only type checks of a single symbol, `uniq` and membrane predicates
+    /// remain wrapped in [`GuardNode::Not`].
+    pub fn clauses(&self) -> Vec<Vec<GuardNode>> {
+        self.dnf(false)
+    }
+
+    fn dnf(&self, negated: bool) -> Vec<Vec<GuardNode>> {
+        match self {
+            GuardNode::Operation(op @ (GuardOperator::Or | GuardOperator::And), lhs, rhs, _) => {
+                // by De Morgan's laws, a negated `&&` is an `||` of the negations and vice versa
+                let (lhs, rhs) = (lhs.dnf(negated), rhs.dnf(negated));
+                if (*op == GuardOperator::Or) != negated {
+                    lhs.into_iter().chain(rhs).collect()
+                } else {
+                    let mut clauses = vec![];
+                    for l in &lhs {
+                        for r in &rhs {
+                            clauses.push(l.iter().chain(r.iter()).cloned().collect());
+                        }
+                    }
+                    clauses
+                }
+            }
+            GuardNode::Not(inner, _) => inner.dnf(!negated),
+            GuardNode::Operation(op, lhs, rhs, span) if negated && op.is_comparison() => {
+                vec![vec![GuardNode::Operation(
+                    op.negate(),
+                    lhs.clone(),
+                    rhs.clone(),
+                    *span,
+                )]]
+            }
+            // `uniq(X, Y)` is about the combination of both, it cannot be split
+            GuardNode::TypeConstraint(Type::Uniq, _, span) if negated => {
+                vec![vec![GuardNode::Not(Box::new(self.clone()), *span)]]
+            }
+            // `!int(X, Y)` holds if either of them is not an int
+            GuardNode::TypeConstraint(ty, args, span) if negated => args
+                .iter()
+                .map(|arg| {
+                    let check = GuardNode::TypeConstraint(*ty, vec![*arg], *span);
+                    vec![GuardNode::Not(Box::new(check), *span)]
+                })
+                .collect(),
+            GuardNode::MemPredicate(_, _, span) if negated => {
+                vec![vec![GuardNode::Not(Box::new(self.clone()), *span)]]
+            }
+            _ => vec![vec![self.clone()]],
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ProcContext {
     pub name: String,
+    /// Where this occurrence of the process context is written.
+    pub span: Span,
     pub type_: Option<Type>,
+    /// The membrane of the head this process context is written directly in.
+    pub membrane: Option<MembraneId>,
+}
+
+/// A temporary variable defined with `with X := ...`.
+#[derive(Debug, Clone)]
+pub struct TempVar {
+    pub name: String,
+    pub value: GuardNode,
+    /// Where the assignment is written.
+    pub span: Span,
 }
 
 #[derive(Debug, Default)]
 pub struct Case {
     pub id: usize,
     pub entity_id: usize,
+    /// Where this case is written, from its first `when` or its `then` to the end of its body.
+    pub span: Span,
     pub constraint: Option<GuardNode>,
+    /// Where the guard is written, from the first `when` clause to the last.
+    pub guard_span: Option<Span>,
+    pub vars: Vec<TempVar>,
     pub body: Membrane,
+    /// Types inferred for each clause of the guard, in the order of [`GuardNode::clauses`].
+    pub guard_types: Vec<GuardTypes>,
+}
+
+/// Types of the links, process contexts and temporary variables of one guard clause.
+#[derive(Debug, Clone, Default)]
+pub struct GuardTypes {
+    pub symbols: HashMap<Symbol, Type>,
+    /// Indexed like [`Case::vars`].
+    pub vars: Vec<Option<Type>>,
 }
 
 #[derive(Debug, Default)]
@@ -71,7 +349,11 @@ pub struct Rule {
     /// The line and column number of this rule in the source file.
     pub line_col: (usize, usize),
 
-    /// The membrane this rule belongs to.
+    /// Where this rule is written.
+    pub span: Span,
+
+    /// The membrane of the processes written directly in this rule: [`RULE_MEMBRANE`] for a
+    /// rule of the program, the root membrane for the initial process.
     pub membrane: MembraneId,
 
     /// The name of this rule.
@@ -79,27 +361,49 @@ pub struct Rule {
     /// Anonymouse rules are given a generated name.
     pub name: String,
 
+    /// The module this rule was loaded from with `use`, if any.
+    pub module: Option<String>,
+
+    /// Rules of higher priority are tried first, set with `@priority(N)`.
+    pub priority: i64,
+
+    /// Whether this rule fires at most once per membrane, set with `@once`.
+    pub once: bool,
+
+    /// Whether this rule is turned off, set with `@disabled`.
+    pub disabled: bool,
+
     /// The pattern of this rule.
     pub pattern: Membrane,
 
     /// The cases of this rule.
     pub cases: Vec<Case>,
     pub case_atoms: Vec<Vec<Atom>>,
-    pub case_links: Vec<HashMap<LinkId, Link>>,
+    pub case_links: Vec<BTreeMap<LinkId, Link>>,
     pub case_mems: Vec<Vec<Membrane>>,
 
     /// Global entity id for pattern, later used for case parsing.
     pub(crate) entity_id: usize,
+    /// Next entity id in the case being parsed, counted on from the pattern.
+    case_entity_id: usize,
     pub(crate) atoms: Vec<Atom>,
-    pub(crate) links: HashMap<LinkId, Link>,
+    pub(crate) links: BTreeMap<LinkId, Link>,
+    /// Links of the head or the case being parsed waiting for their other end, by name.
+    open_links: HashMap<String, LinkId>,
     pub(crate) mems: Vec<Membrane>,
     pub(crate) procs: Vec<ProcContext>,
+    /// Temporary variables of the case being parsed.
+    pub(crate) temp_vars: Vec<String>,
+    /// Names of the annotations given so far.
+    annotations: Vec<String>,
+
+    /// Errors found while parsing this rule.
+    pub(crate) diagnostics: Vec<Diagnostic>,
 }
 
 #[derive(Debug, Clone, Copy)]
 struct RuleContext {
     case: Option<usize>,
-    entity_id: usize,
     /// From which symbol this symbol is generated.
     from: Symbol,
     /// Valid only when `from` is `Some(Symbol::Atom)` or `Some(Symbol::Membrane)`.
@@ -116,33 +420,35 @@ impl Rule {
         }
     }
 
-    pub fn parse(&mut self, pair: pest::iterators::Pair<ParseRule>, ctx: Context) {
+    pub fn parse(&mut self, pair: pest::iterators::Pair<ParseRule>) {
+        self.membrane = RULE_MEMBRANE;
         let mut case_counter = 0;
         for pair in pair.into_inner() {
             match pair.as_rule() {
+                ParseRule::Annotation => self.parse_annotation(pair),
                 ParseRule::RuleName => {
                     self.name = pair.as_str().to_string();
                 }
                 ParseRule::Pattern => {
                     let ctx = RuleContext {
                         case: None,
-                        entity_id: 0,
                         from: Symbol::Rule(0),
                         pos: None,
-                        membrane: ctx.membrane,
+                        membrane: RULE_MEMBRANE,
                     };
                     self.pattern = self.parse_root(pair, ctx);
                 }
                 ParseRule::Case => {
+                    self.case_entity_id = self.entity_id;
+                    self.open_links.clear();
                     let ctx = RuleContext {
                         case: Some(case_counter),
-                        entity_id: self.entity_id,
                         from: Symbol::Rule(0),
                         pos: None,
-                        membrane: ctx.membrane,
+                        membrane: RULE_MEMBRANE,
                     };
                     self.case_atoms.push(Vec::new());
-                    self.case_links.push(HashMap::new());
+                    self.case_links.push(BTreeMap::new());
                     self.case_mems.push(Vec::new());
                     let case = self.parse_case(pair, ctx);
                     self.cases.push(case);
@@ -159,7 +465,60 @@ impl Rule {
         }
     }
 
+    fn parse_annotation(&mut self, pair: pest::iterators::Pair<ParseRule>) {
+        const ANNOTATIONS: [&str; 3] = ["priority", "once", "disabled"];
+        // the pair ends after the whitespace that follows the annotation
+        let start = offset(pair.as_span().start());
+        let span = Span::new(start, start + pair.as_str().trim_end().len());
+        let mut pairs = pair.into_inner();
+        let name = pairs.next().unwrap().as_str();
+        let arg = pairs.next().map(|arg| arg.as_str());
+
+        if self.annotations.iter().any(|a| a == name) {
+            self.diagnostics.push(
+                Diagnostic::error(format!("`@{}` is given more than once", name))
+                    .with_label(span, "repeated here"),
+            );
+            return;
+        }
+        let error = match (name, arg) {
+            ("priority", Some(arg)) => match arg.parse() {
+                Ok(priority) => {
+                    self.priority = priority;
+                    None
+                }
+                Err(_) => Some(format!("priority `{}` is out of range", arg)),
+            },
+            ("priority", None) => Some("`@priority` needs a value, as in `@priority(10)`".into()),
+            ("once" | "disabled", Some(_)) => Some(format!("`@{}` takes no value", name)),
+            ("once", None) => {
+                self.once = true;
+                None
+            }
+            ("disabled", None) => {
+                self.disabled = true;
+                None
+            }
+            _ => {
+                let mut d = Diagnostic::error(format!("unknown rule annotation `@{}`", name))
+                    .with_label(span, "not a rule annotation")
+                    .with_note("the annotations are `@priority(N)`, `@once` and `@disabled`");
+                if let Some(suggestion) = closest_name(name, ANNOTATIONS.into_iter()) {
+                    d = d.with_help(format!("did you mean `@{}`?", suggestion));
+                }
+                self.diagnostics.push(d);
+                return;
+            }
+        };
+        self.annotations.push(name.to_string());
+        if let Some(message) = error {
+            self.diagnostics
+                .push(Diagnostic::error(message).with_label(span, "in this annotation"));
+        }
+    }
+
     fn parse_root(&mut self, pair: pest::iterators::Pair<ParseRule>, ctx: RuleContext) -> Membrane {
+        let root_span = span(pair.as_span());
         let mut process = Vec::new();
         for pair in pair.into_inner() {
             match pair.as_rule() {
@@ -179,6 +538,7 @@ impl Rule {
             id: ctx.membrane,
             process,
             rule_set: vec![],
+            span: root_span,
         }
     }
 
@@ -186,61 +546,89 @@ impl Rule {
 
     /// Parse a guard function constraint.
     fn parse_guard(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
-        for pair in pair.into_inner() {
-            match pair.as_rule() {
-                ParseRule::OrExpr => {
-                    return self.parse_expr(pair);
-                }
-                _ => {
-                    unreachable!("Unexpected rule: {:?}", pair.as_rule());
-                }
+        let pair = pair.into_inner().next().unwrap();
+        match pair.as_rule() {
+            ParseRule::OrExpr => self.parse_expr(pair),
+            _ => {
+                unreachable!("Unexpected rule: {:?}", pair.as_rule());
             }
         }
-        unreachable!()
     }
 
+    /// Parse a binary expression, operators of the same precedence are left associative.
     fn parse_expr(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
-        let lhs_parsed = false;
-        let mut lhs: GuardNode = GuardNode::IntValue(0);
+        let mut lhs: Option<GuardNode> = None;
         let mut op: rule_parser::GuardOperator = rule_parser::GuardOperator::Or;
+        let start = offset(pair.as_span().start());
         for pair in pair.into_inner() {
-            match pair.as_rule() {
-                ParseRule::GuardFuncConstraint => {
-                    return self.parse_guard_func(pair);
-                }
-                ParseRule::Float => {
-                    return GuardNode::FloatValue(pair.as_str().parse().unwrap());
-                }
-                ParseRule::Int => {
-                    return GuardNode::IntValue(pair.as_str().parse().unwrap());
-                }
-                ParseRule::GuardFunctor => {
-                    return GuardNode::Value(self.get_symbol(pair.as_str()));
-                }
-                ParseRule::OrExpr
+            let end = offset(pair.as_span().end());
+            let node = match pair.as_rule() {
+                ParseRule::GuardFuncConstraint => self.parse_guard_func(pair),
+                ParseRule::GuardMemConstraint => self.parse_mem_constraint(pair),
+                ParseRule::Float => GuardNode::FloatValue(self.parse_float(pair)),
+                ParseRule::Int => GuardNode::IntValue(self.parse_int(pair)),
+                ParseRule::Constant => self.parse_constant(pair),
+                ParseRule::GuardFunctor => self.resolve(pair),
+                ParseRule::GuardCall => self.parse_call(pair),
+                ParseRule::NotExpr => self.parse_not(pair),
+                ParseRule::Guard
+                | ParseRule::OrExpr
                 | ParseRule::AndExpr
                 | ParseRule::RelExpr
                 | ParseRule::AddSubExpr
-                | ParseRule::MulDivExpr => {
-                    lhs = if !lhs_parsed {
-                        self.parse_expr(pair)
-                    } else {
-                        let rhs = self.parse_expr(pair);
-                        GuardNode::Operation(op, Box::new(lhs), Box::new(rhs))
-                    };
-                }
+                | ParseRule::MulDivExpr => self.parse_expr(pair),
                 _ => {
                     op = op_map(pair.as_rule());
+                    continue;
                 }
-            }
+            };
+            lhs = Some(match lhs {
+                Some(lhs) => {
+                    GuardNode::Operation(op, Box::new(lhs), Box::new(node), Span::new(start, end))
+                }
+                None => node,
+            });
         }
-        lhs
+        lhs.unwrap()
+    }
+
+    fn parse_not(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
+        let span = span(pair.as_span());
+        // skip the `!` or `not`
+        let pair = pair.into_inner().nth(1).unwrap();
+        let node = match pair.as_rule() {
+            ParseRule::GuardFuncConstraint => self.parse_guard_func(pair),
+            ParseRule::GuardMemConstraint => self.parse_mem_constraint(pair),
+            _ => self.parse_expr(pair),
+        };
+        GuardNode::Not(Box::new(node), span)
+    }
+
+    fn parse_call(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
+        let span = span(pair.as_span());
+        let mut pairs = pair.into_inner();
+        let func = match pairs.next().unwrap().as_str() {
+            "int" => GuardFunction::Int,
+            "float" => GuardFunction::Float,
+            "abs" => GuardFunction::Abs,
+            "sqrt" => GuardFunction::Sqrt,
+            "min" => GuardFunction::Min,
+            "max" => GuardFunction::Max,
+            name => unreachable!("Unexpected function: {}", name),
+        };
+        let args = pairs.map(|pair| self.parse_expr(pair)).collect();
+        GuardNode::Call(func, args, span)
     }
 
     fn parse_guard_func(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
         let mut functor: Type = Type::Ground;
+        let mut check = "";
         let mut args: Vec<Symbol> = Vec::new();
+        let span = span(pair.as_span());
         for pair in pair.into_inner() {
+            if pair.as_rule() != ParseRule::GuardFunctorList {
+                check = pair.as_str();
+            }
             match pair.as_rule() {
                 ParseRule::GuardInt => {
                     functor = Type::Int;
@@ -248,6 +636,9 @@ impl Rule {
                 ParseRule::GuardFloat => {
                     functor = Type::Float;
                 }
+                ParseRule::GuardString => {
+                    functor = Type::String;
+                }
                 ParseRule::GuardUnary => {
                     functor = Type::Unary;
                 }
@@ -261,7 +652,25 @@ impl Rule {
                     for pair in pair.into_inner() {
                         match pair.as_rule() {
                             ParseRule::GuardFunctor => {
-                                args.push(self.get_symbol(pair.as_str()));
+                                let arg_span = super::span(pair.as_span());
+                                match self.resolve(pair) {
+                                    GuardNode::Value(symbol) => args.push(symbol),
+                                    GuardNode::Var(i) => {
+                                        let d = Diagnostic::error(format!(
+                                            "`{}` checks a link or process context of the head, \
+                                             found temporary variable `{}`",
+                                            check, self.temp_vars[i]
+                                        ))
+                                        .with_label(arg_span, "temporary variable")
+                                        .with_note(
+                                            "the type of a temporary variable follows from \
+                                             its expression",
+                                        );
+                                        self.diagnostics.push(d);
+                                    }
+                                    // an unknown name, already reported
+                                    _ => {}
+                                }
                             }
                             _ => {
                                 unreachable!("Unexpected rule: {:?}", pair.as_rule());
@@ -275,24 +684,182 @@ impl Rule {
             }
         }
 
-        GuardNode::TypeConstraint(functor, args)
+        GuardNode::TypeConstraint(functor, args, span)
+    }
+
+    fn parse_mem_constraint(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
+        let span = super::span(pair.as_span());
+        let mut pairs = pair.into_inner();
+        let func = pairs.next().unwrap();
+        let arg = pairs.next().unwrap();
+        let predicate = match func.as_str() {
+            "natoms" => MemPredicate::NAtoms(self.parse_count(pairs.next().unwrap())),
+            "nmems" => MemPredicate::NMems(self.parse_count(pairs.next().unwrap())),
+            "name" => {
+                let name = pairs.next().unwrap();
+                MemPredicate::Name(match name.as_rule() {
+                    ParseRule::Constant => constant(&name).unwrap_or_default(),
+                    _ => name.as_str().to_string(),
+                })
+            }
+            "norules" => MemPredicate::NoRules,
+            name => unreachable!("Unexpected predicate: {}", name),
+        };
+
+        let arg_span = super::span(arg.as_span());
+        let found = match self.resolve(arg) {
+            GuardNode::Value(Symbol::ProcContext(i)) => match self.procs[i].membrane {
+                Some(mem) => return GuardNode::MemPredicate(predicate, mem, span),
+                None => format!("`${}`, which is not in a membrane", self.procs[i].name),
+            },
+            GuardNode::Value(Symbol::Link(id)) => format!("link `{}`", self.links[&id].name),
+            GuardNode::Var(i) => format!("temporary variable `{}`", self.temp_vars[i]),
+            // an unknown name, already reported
+            _ => return GuardNode::IntValue(0),
+        };
+        self.diagnostics.push(
+            Diagnostic::error(format!(
+                "`{}` expects the process context of a membrane, found {}",
+                func.as_str(),
+                found
+            ))
+            .with_label(arg_span, "not a membrane of the head")
+            .with_help(format!(
+                "write the membrane in the head as `{{$p}}` and pass `$p` to `{}`",
+                func.as_str()
+            )),
+        );
+        GuardNode::IntValue(0)
+    }
+
+    /// Parse an integer literal of a guard, reporting one out of range.
+    fn parse_int(&mut self, pair: pest::iterators::Pair<ParseRule>) -> i64 {
+        super::parse_int(pair.as_str(), span(pair.as_span())).unwrap_or_else(|d| {
+            self.diagnostics.push(d);
+            0
+        })
+    }
+
+    /// Parse a float literal of a guard, reporting one out of range.
+    fn parse_float(&mut self, pair: pest::iterators::Pair<ParseRule>) -> f64 {
+        super::parse_float(pair.as_str(), span(pair.as_span())).unwrap_or_else(|d| {
+            self.diagnostics.push(d);
+            0.0
+        })
+    }
+
+    /// Parse a constant used as a number in a guard.
+    fn parse_constant(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
+        // an undefined constant is already reported
+        let Some(value) = constant(&pair) else {
+            return GuardNode::IntValue(0);
+        };
+        // values are checked to be atom names, so a leading digit makes a number
+        if value.starts_with(|c: char| c.is_ascii_digit()) {
+            let span = span(pair.as_span());
+            let number = match value.contains('.') {
+                true => super::parse_float(&value, span).map(GuardNode::FloatValue),
+                false => super::parse_int(&value, span).map(GuardNode::IntValue),
+            };
+            return number.unwrap_or_else(|d| {
+                self.diagnostics.push(d);
+                GuardNode::IntValue(0)
+            });
+        }
+        self.diagnostics.push(
+            Diagnostic::error(format!(
+                "constant `{}` is `{}`, which is not a number",
+                pair.as_str(),
+                value
+            ))
+            .with_label(span(pair.as_span()), "used as a number here"),
+        );
+        GuardNode::IntValue(0)
+    }
+
+    /// Parse the number of atoms or membranes a membrane predicate expects.
+    fn parse_count(&mut self, pair: pest::iterators::Pair<ParseRule>) -> usize {
+        if pair.as_rule() == ParseRule::Int {
+            return self.parse_int(pair) as usize;
+        }
+        let pair_span = span(pair.as_span());
+        let text = pair.as_str().to_string();
+        match self.parse_constant(pair) {
+            GuardNode::IntValue(n) => n as usize,
+            _ => {
+                self.diagnostics.push(
+                    Diagnostic::error(format!("constant `{}` is not an integer", text))
+                        .with_label(pair_span, "expected a count here"),
+                );
+                0
+            }
+        }
+    }
+
+    /// Resolve a name used in a guard to a link or process context of the head,
+    /// or to a temporary variable of the current case.
+    fn resolve(&mut self, pair: pest::iterators::Pair<ParseRule>) -> GuardNode {
+        let name = pair.as_str();
+        if let Some(symbol) = self.get_symbol(name) {
+            return GuardNode::Value(symbol);
+        }
+        if let Some(i) = self.temp_vars.iter().position(|v| v == name) {
+            return GuardNode::Var(i);
+        }
+        let d = self.unknown_name(name, span(pair.as_span()), "guard");
+        self.diagnostics.push(d);
+        // placeholder, the rule is rejected anyway
+        GuardNode::IntValue(0)
     }
 
-    fn get_symbol(&mut self, name: &str) -> Symbol {
+    fn get_symbol(&self, name: &str) -> Option<Symbol> {
         if let Some(name) = name.strip_prefix('$') {
             for (i, proc) in self.procs.iter().enumerate() {
                 if proc.name == name {
-                    return Symbol::ProcContext(i);
+                    return Some(Symbol::ProcContext(i));
                 }
             }
         } else {
             for (i, link) in self.links.iter() {
                 if link.name == name {
-                    return Symbol::Link(*i);
+                    return Some(Symbol::Link(*i));
                 }
             }
         }
-        panic!("Symbol not found: {}", name);
+        None
+    }
+
+    /// Report a name that is neither in the head of this rule nor a temporary variable.
+    fn unknown_name(&self, name: &str, span: Span, place: &str) -> Diagnostic {
+        let mut links: Vec<&str> = self.links.values().map(|l| l.name.as_str()).collect();
+        links.sort();
+        links.dedup();
+        let procs: Vec<String> = self.procs.iter().map(|p| format!("${}", p.name)).collect();
+        let vars: Vec<&str> = self.temp_vars.iter().map(String::as_str).collect();
+
+        let mut d = Diagnostic::error(format!("cannot find `{}` in rule `{}`", name, self.name))
+            .with_label(
+                span,
+                "not found in the head of this rule or its `with` clauses",
+            );
+        for (kind, names) in [
+            ("links", links.join(", ")),
+            ("process contexts", procs.join(", ")),
+            ("temporary variables", vars.join(", ")),
+        ] {
+            if !names.is_empty() {
+                d = d.with_note(format!("{} in scope of this {}: {}", kind, place, names));
+            }
+        }
+        let candidates = links
+            .iter()
+            .copied()
+            .chain(procs.iter().map(String::as_str))
+            .chain(vars.iter().copied());
+        if let Some(suggestion) = closest_name(name, candidates) {
+            d = d.with_help(format!("did you mean `{}`?", suggestion));
+        }
+        d
     }
 
     // Parsing pattern and body
@@ -305,8 +872,20 @@ impl Rule {
         let mut list: Vec<Symbol> = Vec::new();
         for pair in pair.into_inner() {
             match pair.as_rule() {
+                // a rule of a membrane the body creates, given to it like one written outside
+                ParseRule::Rule if ctx.case.is_some() => list.push(parse_rule(pair)),
                 ParseRule::Rule => {
-                    todo!()
+                    self.diagnostics.push(
+                        Diagnostic::error("a rule cannot be matched by the head of a rule")
+                            .with_label(span(pair.as_span()), "in the head of a rule")
+                            .with_help("write the rule in a membrane of the body"),
+                    );
+                }
+                ParseRule::ModuleDecl | ParseRule::UseDecl | ParseRule::IncludeDecl => {
+                    self.diagnostics.push(
+                        Diagnostic::error("modules and files can only be loaded outside of rules")
+                            .with_label(span(pair.as_span()), "in the body of a rule"),
+                    );
                 }
                 ParseRule::DeclarationList => {
                     list.append(&mut self.parse_declaration_list(pair, ctx));
@@ -338,6 +917,14 @@ impl Rule {
         for pair in pair.into_inner() {
             match pair.as_rule() {
                 ParseRule::Declaration => {
+                    // arguments of an atom are connected to consecutive ports
+                    let ctx = match ctx.from {
+                        Symbol::Atom(_) => RuleContext {
+                            pos: Some(symbols.len()),
+                            ..ctx
+                        },
+                        _ => ctx,
+                    };
                     symbols.push(self.parse_declaration(pair, ctx));
                 }
                 _ => {
@@ -353,24 +940,29 @@ impl Rule {
         pair: pest::iterators::Pair<ParseRule>,
         ctx: RuleContext,
     ) -> Symbol {
-        for pair in pair.into_inner() {
-            match pair.as_rule() {
-                ParseRule::UnitAtom => {
-                    return self.parse_unit_atom(pair, ctx);
-                }
-                ParseRule::Context => {
-                    // extract name
-                    let name = pair.as_str().to_string()[1..].to_string();
-                    let context = ProcContext { name, type_: None };
-                    self.procs.push(context);
-                    return Symbol::ProcContext(self.procs.len() - 1);
-                }
-                _ => {
-                    unreachable!("Unexpected rule: {:?}", pair.as_rule());
-                }
+        let pair = pair.into_inner().next().unwrap();
+        match pair.as_rule() {
+            ParseRule::UnitAtom => self.parse_unit_atom(pair, ctx),
+            ParseRule::Context => {
+                // extract name
+                let name = pair.as_str().to_string()[1..].to_string();
+                let membrane = match (ctx.case, ctx.from) {
+                    (None, Symbol::Membrane(id)) => Some(id),
+                    _ => None,
+                };
+                let context = ProcContext {
+                    name,
+                    span: span(pair.as_span()),
+                    type_: None,
+                    membrane,
+                };
+                self.procs.push(context);
+                Symbol::ProcContext(self.procs.len() - 1)
+            }
+            _ => {
+                unreachable!("Unexpected rule: {:?}", pair.as_rule());
             }
         }
-        unreachable!();
     }
 
     fn parse_unit_atom(
@@ -378,33 +970,25 @@ impl Rule {
         pair: pest::iterators::Pair<ParseRule>,
         ctx: RuleContext,
     ) -> Symbol {
-        for pair in pair.into_inner() {
-            match pair.as_rule() {
-                ParseRule::Atom => {
-                    return self.parse_atom(pair, ctx);
-                }
-                ParseRule::Membrane => {
-                    return self.parse_membrane(pair, ctx);
-                }
-                ParseRule::Link => match ctx.from {
-                    Symbol::Rule(id) | Symbol::Membrane(id) => {
-                        panic!("Top-level link is not allowed in rule {}", id);
-                    }
-                    _ => {
-                        return self.parse_link(pair, ctx);
-                    }
-                },
-                _ => {
-                    unreachable!();
+        let pair = pair.into_inner().next().unwrap();
+        match pair.as_rule() {
+            ParseRule::Atom => self.parse_atom(pair, ctx),
+            ParseRule::Membrane => self.parse_membrane(pair, ctx),
+            ParseRule::Link => match ctx.from {
+                Symbol::Rule(id) | Symbol::Membrane(id) => {
+                    panic!("Top-level link is not allowed in rule {}", id);
                 }
+                _ => self.parse_link(pair, ctx),
+            },
+            _ => {
+                unreachable!();
             }
         }
-        unreachable!();
     }
 
     fn parse_link(&mut self, pair: pest::iterators::Pair<ParseRule>, ctx: RuleContext) -> Symbol {
         let mut name = "".to_string();
-        let pos = pair.as_span().start_pos().pos();
+        let link_span = span(pair.as_span());
         for pair in pair.into_inner() {
             match pair.as_rule() {
                 ParseRule::LinkName => {
@@ -416,39 +1000,42 @@ impl Rule {
             }
         }
 
-        // find if there is a link with the same name
-        for (k, v) in self.links.iter_mut() {
-            if v.name == name {
-                v.link2 = Some((ctx.from, ctx.pos.unwrap()));
-                return Symbol::Link(*k);
-            }
+        // connect a link with the same name waiting for its other end,
+        // links of a case body are kept apart from the links of the head
+        let links = match ctx.case {
+            Some(case) => &mut self.case_links[case],
+            None => &mut self.links,
+        };
+        if let Some(id) = self.open_links.remove(&name) {
+            let link = links.get_mut(&id).unwrap();
+            link.link2 = Some((ctx.from, ctx.pos.unwrap()));
+            link.span2 = Some(link_span);
+            return Symbol::Link(id);
         }
-        let id = self.links.len();
+        let id = links.len();
+        self.open_links.insert(name.clone(), id);
         let link = Link {
             name,
             link1: Some((ctx.from, ctx.pos.unwrap())),
             link2: None,
-            pos1: Some(pos),
-            pos2: None,
+            span1: Some(link_span),
+            span2: None,
         };
-        if ctx.case.is_some() {
-            self.case_links[ctx.case.unwrap()].insert(id, link);
-        } else {
-            self.links.insert(id, link);
-        }
+        links.insert(id, link);
         Symbol::Link(id)
     }
 
     fn parse_membrane(
         &mut self,
         pair: pest::iterators::Pair<ParseRule>,
-        mut ctx: RuleContext,
+        ctx: RuleContext,
     ) -> Symbol {
+        let mem_span = span(pair.as_span());
         let mut name = "".to_string();
         let mut process: Vec<Symbol> = Vec::new();
         let id = if ctx.case.is_some() {
-            let id = ctx.entity_id;
-            ctx.entity_id += 1;
+            let id = self.case_entity_id;
+            self.case_entity_id += 1;
             id
         } else {
             let id = self.entity_id;
@@ -460,6 +1047,9 @@ impl Rule {
                 ParseRule::AtomName => {
                     name = pair.as_str().to_string();
                 }
+                ParseRule::Constant => {
+                    name = constant(&pair).unwrap_or_default();
+                }
                 ParseRule::WorldProcessList => {
                     process.append(&mut self.parse_world_process_list(
                         pair,
@@ -475,27 +1065,37 @@ impl Rule {
                 }
             }
         }
+        let mut rule_set = vec![];
+        for symbol in process.iter() {
+            if let Symbol::Rule(id) = symbol {
+                rule_set.push(*id);
+            }
+        }
+        process.retain(|symbol| !matches!(symbol, Symbol::Rule(_)));
+
         let membrane = Membrane {
             membrane: ctx.membrane,
             id,
             name,
             process,
-            rule_set: vec![],
+            rule_set,
+            span: mem_span,
         };
-        if ctx.case.is_some() {
-            self.case_mems[ctx.case.unwrap()].push(membrane);
+        if let Some(case) = ctx.case {
+            self.case_mems[case].push(membrane);
         } else {
             self.mems.push(membrane);
         }
         Symbol::Membrane(id)
     }
 
-    fn parse_atom(&mut self, pair: pest::iterators::Pair<ParseRule>, mut ctx: RuleContext) -> Symbol {
+    fn parse_atom(&mut self, pair: pest::iterators::Pair<ParseRule>, ctx: RuleContext) -> Symbol {
         let mut name: String = "".to_string();
+        let mut span = Span::default();
         let mut process: Vec<Symbol> = Vec::new();
         let id = if ctx.case.is_some() {
-            let id = ctx.entity_id;
-            ctx.entity_id += 1;
+            let id = self.case_entity_id;
+            self.case_entity_id += 1;
             id
         } else {
             let id = self.entity_id;
@@ -505,8 +1105,18 @@ impl Rule {
         let mut pos = 0;
         for pair in pair.into_inner() {
             match pair.as_rule() {
-                ParseRule::AtomName => {
-                    name = pair.as_str().to_string();
+                ParseRule::AtomName | ParseRule::Constant => {
+                    span = super::span(pair.as_span());
+                    let text = match pair.as_rule() {
+                        ParseRule::Constant => {
+                            constant(&pair).unwrap_or_else(|| pair.as_str().to_string())
+                        }
+                        _ => pair.as_str().to_string(),
+                    };
+                    name = atom_name(&text, span).unwrap_or_else(|d| {
+                        self.diagnostics.push(d);
+                        text
+                    });
                 }
                 ParseRule::DeclarationList => {
                     process.append(&mut self.parse_declaration_list(
@@ -527,23 +1137,17 @@ impl Rule {
             }
         }
 
-        let atom = if process.is_empty() {
-            Atom {
-                membrane: ctx.membrane,
-                id,
-                name,
-                links: vec![],
-            }
-        } else {
-            Atom {
-                membrane: ctx.membrane,
-                id,
-                name,
-                links: process,
-            }
+        // an atom written as an argument has one more link, to its parent
+        let arity = process.len() + matches!(ctx.from, Symbol::Atom(_)) as usize;
+        let atom = Atom {
+            membrane: ctx.membrane,
+            id,
+            functor: intern(&name, arity),
+            links: process,
+            span,
         };
-        if ctx.case.is_some() {
-            self.case_atoms[ctx.case.unwrap()].push(atom);
+        if let Some(case) = ctx.case {
+            self.case_atoms[case].push(atom);
         } else {
             self.atoms.push(atom);
         }
@@ -556,16 +1160,45 @@ impl Rule {
     fn parse_case(&mut self, pair: pest::iterators::Pair<ParseRule>, ctx: RuleContext) -> Case {
         let mut case = Case {
             id: ctx.case.unwrap(),
+            span: span(pair.as_span()),
             ..Default::default()
         };
+        self.temp_vars.clear();
         for pair in pair.into_inner() {
             match pair.as_rule() {
                 ParseRule::Guard => {
-                    case.constraint = Some(self.parse_guard(pair));
+                    let span = span(pair.as_span());
+                    case.guard_span = Some(match case.guard_span {
+                        Some(prev) => Span::new(prev.start, span.end),
+                        None => span,
+                    });
+                    let guard = self.parse_guard(pair);
+                    // consecutive `when` clauses must all hold
+                    case.constraint = Some(match case.constraint.take() {
+                        Some(prev) => GuardNode::Operation(
+                            GuardOperator::And,
+                            Box::new(prev),
+                            Box::new(guard),
+                            span,
+                        ),
+                        None => guard,
+                    });
+                }
+                ParseRule::VarGuard => {
+                    for pair in pair.into_inner() {
+                        let span = span(pair.as_span());
+                        let mut inner = pair.into_inner();
+                        let name = inner.next().unwrap().as_str().to_string();
+                        let value = self.parse_expr(inner.next().unwrap());
+                        self.temp_vars.push(name.clone());
+                        case.vars.push(TempVar { name, value, span });
+                    }
                 }
-                ParseRule::VarGuard => {}
                 ParseRule::Body => {
+                    let first = self.procs.len();
                     case.body = self.parse_root(pair, ctx);
+                    self.check_body_names(case.id);
+                    self.check_body_contexts(first);
                 }
                 ParseRule::WHEN | ParseRule::WITH | ParseRule::THEN => {
                     // ignore
@@ -579,6 +1212,66 @@ impl Rule {
     }
 }
 
+impl Rule {
+    /// A name used only once in a body must connect to the head or refer to a temporary variable.
+    fn check_body_names(&mut self, case: usize) {
+        let mut unknown: Vec<(String, Span)> = vec![];
+        for link in self.case_links[case].values() {
+            if link.link2.is_some()
+                || self.get_symbol(&link.name).is_some()
+                || self.temp_vars.contains(&link.name)
+            {
+                continue;
+            }
+            unknown.push((link.name.clone(), link.span1.unwrap_or_default()));
+        }
+        unknown.sort_by_key(|(_, span)| *span);
+        for (name, span) in unknown {
+            let d = self.unknown_name(&name, span, "body");
+            self.diagnostics.push(d);
+        }
+    }
+}
+
+impl Rule {
+    /// A process context of a body, the ones from `first` on, carries over what the process
+    /// context of the same name matched in a membrane of the head, so there must be one,
+    /// and what it matched is carried over only once.
+    fn check_body_contexts(&mut self, first: usize) {
+        let mut seen: Vec<&str> = vec![];
+        let mut diagnostics = vec![];
+        for proc in &self.procs[first..] {
+            let in_head = self.procs[..first]
+                .iter()
+                .any(|head| head.name == proc.name && head.membrane.is_some());
+            if !in_head {
+                diagnostics.push(
+                    Diagnostic::error(format!(
+                        "cannot find `${}` in a membrane of the head of rule `{}`",
+                        proc.name, self.name
+                    ))
+                    .with_label(proc.span, "nothing to carry over")
+                    .with_note(
+                        "a process context of the body carries over what the one of the same \
+                         name matched in the head",
+                    ),
+                );
+            } else if seen.contains(&proc.name.as_str()) {
+                diagnostics.push(
+                    Diagnostic::error(format!(
+                        "process context `${}` is used more than once in the body",
+                        proc.name
+                    ))
+                    .with_label(proc.span, "used again here")
+                    .with_note("what it matched can only be carried over once"),
+                );
+            }
+            seen.push(&proc.name);
+        }
+        self.diagnostics.append(&mut diagnostics);
+    }
+}
+
 fn op_map(rule: ParseRule) -> GuardOperator {
     match rule {
         ParseRule::OR => GuardOperator::Or,
@@ -594,6 +1287,16 @@ fn op_map(rule: ParseRule) -> GuardOperator {
         ParseRule::MUL => GuardOperator::Mul,
         ParseRule::DIV => GuardOperator::Div,
         ParseRule::MOD => GuardOperator::Mod,
+        ParseRule::FADD => GuardOperator::FAdd,
+        ParseRule::FSUB => GuardOperator::FSub,
+        ParseRule::FMUL => GuardOperator::FMul,
+        ParseRule::FDIV => GuardOperator::FDiv,
+        ParseRule::FEQ => GuardOperator::FEq,
+        ParseRule::FNE => GuardOperator::FNeq,
+        ParseRule::FLT => GuardOperator::FLt,
+        ParseRule::FLE => GuardOperator::FLe,
+        ParseRule::FGT => GuardOperator::FGt,
+        ParseRule::FGE => GuardOperator::FGe,
         _ => {
             unreachable!("Unexpected rule: {:?}", rule);
         }
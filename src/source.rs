@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+
+use crate::diagnostic::line_col;
+
+pub type FileId = usize;
+
+/// A position in a source file, lines and columns start at 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub file: FileId,
+    pub line: usize,
+    pub column: usize,
+}
+
+/// A source file of the program.
+#[derive(Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+    /// Offset of the first byte of this file in the [`SourceMap`].
+    pub start: usize,
+}
+
+/// All source files of a program.
+///
+/// The files are laid out one after another in a single range of offsets,
+/// so a [`Span`](crate::diagnostic::Span) tells both the file and the position in it.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file and return its id.
+    pub fn add(&mut self, path: impl Into<PathBuf>, text: String) -> FileId {
+        // leave a gap, so the end of a file does not point into the next one
+        let start = self.files.last().map_or(0, |f| f.start + f.text.len() + 1);
+        self.files.push(SourceFile {
+            path: path.into(),
+            text,
+            start,
+        });
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, id: FileId) -> &SourceFile {
+        &self.files[id]
+    }
+
+    /// The files in the order they were added, which is the order of their offsets.
+    pub fn iter(&self) -> impl Iterator<Item = &SourceFile> {
+        self.files.iter()
+    }
+
+    /// Find a file that has already been added by its path.
+    pub fn find(&self, path: &Path) -> Option<FileId> {
+        self.files.iter().position(|f| f.path == path)
+    }
+
+    /// The file containing the given offset.
+    pub fn lookup(&self, offset: usize) -> Option<&SourceFile> {
+        self.files
+            .iter()
+            .rev()
+            .find(|f| f.start <= offset && offset <= f.start + f.text.len())
+    }
+
+    /// The file, line and column of the given offset.
+    pub fn location(&self, offset: usize) -> Option<Location> {
+        let file = self
+            .files
+            .iter()
+            .rposition(|f| f.start <= offset && offset <= f.start + f.text.len())?;
+        let source = &self.files[file];
+        let (line, column) = line_col(&source.text, offset - source.start);
+        Some(Location { file, line, column })
+    }
+}
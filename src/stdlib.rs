@@ -0,0 +1,23 @@
+//! The standard library, modules that `use` finds by their name without a file.
+
+use std::path::PathBuf;
+
+/// The modules of the standard library and their source.
+pub const MODULES: &[(&str, &str)] = &[
+    ("int", include_str!("stdlib/int.lmn")),
+    ("list", include_str!("stdlib/list.lmn")),
+    ("set", include_str!("stdlib/set.lmn")),
+];
+
+/// The source of the standard library module `name`.
+pub fn module(name: &str) -> Option<&'static str> {
+    MODULES
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, src)| *src)
+}
+
+/// The path a module of the standard library is shown with in messages.
+pub fn path(name: &str) -> PathBuf {
+    PathBuf::from(format!("<stdlib>/{}.lmn", name))
+}
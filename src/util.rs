@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+
 use colored::Colorize;
 
 use crate::parser::{self, data::Symbol};
@@ -8,12 +10,20 @@ pub fn print_indent(indent: usize) {
     }
 }
 
+/// Print the constants defined with `-D`.
+pub fn print_constants(constants: &BTreeMap<String, String>) {
+    for (name, value) in constants {
+        println!("{} #{} = {}", "Constant".bold().yellow(), name, value);
+    }
+}
+
 pub fn print_result(s: &Symbol, indent: usize) {
     match s {
         Symbol::Atom(a) => unsafe {
             let atom = parser::ATOMS.get().unwrap().get(a).unwrap();
             print_indent(indent);
-            println!("{} id:{} name:{}", "Atom".bold().blue(), atom.id, atom.name);
+            let functor = parser::FUNCTORS.get().unwrap().get(atom.functor);
+            println!("{} id:{} name:{}", "Atom".bold().blue(), atom.id, functor);
 
             for s in &atom.links {
                 print_result(s, indent + 1);
@@ -58,3 +68,35 @@ pub fn print_result(s: &Symbol, indent: usize) {
         Symbol::ProcContext(_) => {}
     }
 }
+
+/// Levenshtein distance between two strings.
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Pick the candidate closest to `name`, if any is close enough to be a likely typo.
+pub fn closest_name<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let len = name.chars().count();
+    let limit = usize::max(1, len / 3);
+    candidates
+        .map(|c| (edit_distance(name, c), c))
+        // a single replaced character of a one-letter name is not a typo
+        .filter(|(d, c)| *d <= limit && *d < usize::max(len, c.chars().count()))
+        .min()
+        .map(|(_, c)| c)
+}
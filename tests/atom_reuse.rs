@@ -0,0 +1,95 @@
+mod common;
+
+use common::{compile, compile_optimized, rule};
+
+#[test]
+fn atom_reuse() {
+    const SOURCE: &str = "a(1, 2), c(3, 4), q(5);
+rename: a(X, Y) then b(X, Y);
+swap: b(X, Y) then c(Y, X);
+grow: c(X, Y), q(Z) then c(X, A), d(A, Y), r(Z);";
+    let il = compile(SOURCE);
+    // the heads keep their order
+    let optimized = compile_optimized(SOURCE, 1, &["PatternOptimizer"]);
+
+    assert_eq!(
+        rule(&il, "rename"),
+        "Rule rename
+Registers 3
+Block 0
+find_atom\t1, 0, a, 2
+branch  \t1
+fail
+Block 1
+commit
+remove_atom\t1, 0
+new_atom\t2, 0, b, 2
+relink\t2, 0, 1, 0, 0
+relink\t2, 1, 1, 1, 0
+proceed
+"
+    );
+    // the atom keeps both of its links and only changes its functor
+    assert_eq!(
+        rule(&optimized, "rename"),
+        "Rule rename
+Registers 2
+Block 0
+find_atom\t1, 0, a, 2
+branch  \t1
+fail
+Block 1
+commit
+alter_functor\t1, b, 2
+proceed
+"
+    );
+
+    // each port of the created atom takes the link of the other one, which a reused atom would
+    // have lost by then
+    assert_eq!(rule(&optimized, "swap"), rule(&il, "swap"));
+
+    assert_eq!(
+        rule(&il, "grow"),
+        "Rule grow
+Registers 5
+Block 0
+find_atom\t1, 0, c, 2
+find_atom\t2, 0, q, 1
+branch  \t1
+fail
+Block 1
+commit
+remove_atom\t2, 0
+remove_atom\t1, 0
+new_atom\t3, 0, c, 2
+relink\t3, 0, 1, 0, 0
+new_atom\t4, 0, d, 2
+new_link\t3, 1, 4, 0, 0
+relink\t4, 1, 1, 1, 0
+new_atom\t1, 0, r, 1
+relink\t1, 0, 2, 0, 0
+proceed
+"
+    );
+    // the old `c` becomes `d`, which keeps `Y`, and `q` becomes `r`, which keeps `Z`
+    assert_eq!(
+        rule(&optimized, "grow"),
+        "Rule grow
+Registers 4
+Block 0
+find_atom\t1, 0, c, 2
+find_atom\t2, 0, q, 1
+branch  \t1
+fail
+Block 1
+commit
+new_atom\t3, 0, c, 2
+relink\t3, 0, 1, 0, 0
+alter_functor\t1, d, 2
+new_link\t3, 1, 1, 0, 0
+alter_functor\t2, r, 1
+proceed
+"
+    );
+}
@@ -0,0 +1,22 @@
+mod common;
+
+use liblmntalc::codegen::binary::{MAGIC, VERSION};
+
+#[test]
+fn binary_files() {
+    let binary = common::compile_binary("a;\nr: a then b;\n");
+    let u32_at = |at: usize| u32::from_le_bytes(binary[at..at + 4].try_into().unwrap());
+
+    assert_eq!(&binary[..MAGIC.len()], MAGIC);
+    let mut at = MAGIC.len();
+    assert_eq!(binary[at], VERSION);
+    at += 1;
+    // one file, its path, its start and the start of its second and third lines
+    assert_eq!(u32_at(at), 1);
+    assert_eq!(u32_at(at + 4), 8);
+    assert_eq!(&binary[at + 8..at + 16], b"test.lmn");
+    at += 16;
+    assert_eq!(u32_at(at), 0);
+    assert_eq!(u32_at(at + 4), 2);
+    assert_eq!((u32_at(at + 8), u32_at(at + 12)), (3, 16));
+}
@@ -0,0 +1,13 @@
+mod common;
+
+#[test]
+fn body_context() {
+    let errors = common::errors("m{a}; r: m{a, $p} then n{$p}, k{$p, $q};");
+    assert_eq!(
+        errors,
+        [
+            "process context `$p` is used more than once in the body",
+            "cannot find `$q` in a membrane of the head of rule `r`",
+        ]
+    );
+}
@@ -0,0 +1,18 @@
+mod common;
+
+use common::{compile, rule};
+
+#[test]
+fn body_membrane() {
+    let il = compile("{a; r: a then {b}};");
+    let r = rule(&il, "r");
+
+    // `b` is created in the membrane the body creates, not in the one the rule is applied in
+    let operands = |op: &str| -> Vec<&str> {
+        let line = r.lines().find(|l| l.starts_with(op)).unwrap();
+        line.split_once('\t').unwrap().1.split(", ").collect()
+    };
+    let mem = operands("new_mem")[0];
+    assert_ne!(mem, "0");
+    assert_eq!(operands("new_atom")[1], mem, "in\n{}", r);
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{compile, errors, rule};
+
+#[test]
+fn body_rule() {
+    let il = compile("a, {x; t: x then y}; r: a then {b; s: b then c};");
+
+    // the membrane `r` creates gets a rule set of its own, holding `s`
+    let load = rule(&il, "r")
+        .lines()
+        .find_map(|l| l.strip_prefix("load_rule_set\t"))
+        .unwrap();
+    let (_, rule_set) = load.split_once(", ").unwrap();
+    let header = format!("RuleSet {}\nRule s\n", rule_set);
+    assert!(il.contains(&header), "no `{}` in\n{}", header.trim(), il);
+    assert_eq!(
+        rule(&il, "s"),
+        "Rule s
+Registers 2
+Block 0
+find_atom\t1, 0, b, 0
+branch  \t1
+fail
+Block 1
+commit
+remove_atom\t1, 0
+new_atom\t1, 0, c, 0
+proceed
+"
+    );
+
+    assert_eq!(
+        errors("a; r: a, {s: b then c} then a;"),
+        ["a rule cannot be matched by the head of a rule"]
+    );
+}
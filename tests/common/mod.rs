@@ -0,0 +1,81 @@
+// each test uses only some of these helpers
+#![allow(dead_code)]
+
+use liblmntalc::{
+    analysis::{self, AnalysisOptions},
+    codegen::ILGenerator,
+    optimizer::OptimizerManager,
+    parser::{data::Symbol, parse_lmntal, ParseOptions},
+    source::SourceMap,
+};
+
+/// Compile a program through the whole pipeline and return its IL as text.
+///
+/// Fails on any diagnostic, warnings included, and on malformed IL, such as a port of an atom
+/// the body creates left without a link.
+pub fn compile(source: &str) -> String {
+    generate(source).0.to_string()
+}
+
+/// Like [`compile`], then run the optimizers enabled up to `level` on the IL,
+/// but the ones named in `disables`.
+pub fn compile_optimized(source: &str, level: u8, disables: &[&str]) -> String {
+    let (mut gen, sources) = generate(source);
+    let mut optimizers = OptimizerManager::with_defaults(level);
+    for name in disables {
+        optimizers.disable(name).unwrap();
+    }
+    if let Err(malformed) = optimizers.optimize(&mut gen) {
+        panic!("{}", render(&malformed, &sources));
+    }
+    gen.to_string()
+}
+
+fn generate(source: &str) -> (ILGenerator, SourceMap) {
+    colored::control::set_override(false);
+    let mut sources = SourceMap::new();
+    let file = sources.add("test.lmn", source.to_string());
+    let root = match parse_lmntal(&mut sources, file, &ParseOptions::default()) {
+        Ok(root) => root,
+        Err(diagnostics) => panic!("{}", render(&diagnostics, &sources)),
+    };
+    let Symbol::Membrane(id) = root else {
+        unreachable!()
+    };
+    let diagnostics = analysis::analyze(id, &AnalysisOptions::default());
+    assert!(diagnostics.is_empty(), "{}", render(&diagnostics, &sources));
+    let mut gen = ILGenerator::default();
+    gen.gen(root);
+    let malformed = gen.verify();
+    assert!(malformed.is_empty(), "{}", render(&malformed, &sources));
+    (gen, sources)
+}
+
+/// Like [`compile`], encoding the IL in the binary format.
+pub fn compile_binary(source: &str) -> Vec<u8> {
+    let (gen, sources) = generate(source);
+    gen.to_binary(&sources)
+}
+
+/// The messages of the diagnostics the parser reports on a program it rejects.
+pub fn errors(source: &str) -> Vec<String> {
+    let mut sources = SourceMap::new();
+    let file = sources.add("test.lmn", source.to_string());
+    match parse_lmntal(&mut sources, file, &ParseOptions::default()) {
+        Ok(_) => panic!("the program is accepted"),
+        Err(diagnostics) => diagnostics.into_iter().map(|d| d.message).collect(),
+    }
+}
+
+/// The IL of the rule named `name`.
+pub fn rule<'a>(il: &'a str, name: &str) -> &'a str {
+    let start = il
+        .find(&format!("Rule {}\n", name))
+        .unwrap_or_else(|| panic!("no rule `{}` in\n{}", name, il));
+    let rest = &il[start..];
+    rest.find("\n\n").map_or(rest, |end| &rest[..=end])
+}
+
+fn render(diagnostics: &[liblmntalc::diagnostic::Diagnostic], sources: &SourceMap) -> String {
+    diagnostics.iter().map(|d| d.render(sources)).collect()
+}
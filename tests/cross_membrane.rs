@@ -0,0 +1,24 @@
+mod common;
+
+use common::{compile, errors};
+
+#[test]
+fn cross_membrane() {
+    const ERROR: &str = "connects atoms in different membranes without a proxy";
+
+    // the initial process and the bodies of rules agree
+    assert_eq!(errors("{x(Y)}, y(Y);"), [format!("link `Y` {}", ERROR)]);
+    assert_eq!(
+        errors("a, b; r: a then {x(Y)}, y(Y);"),
+        [format!("link `Y` {}", ERROR)]
+    );
+    // a link of the head going on into a membrane the body creates
+    assert_eq!(
+        errors("c(X), d(X); r: c(X) then {e(X)};"),
+        [format!("link `X` {}", ERROR)]
+    );
+
+    // links within a membrane of the body, and the ones carried over with its contents
+    compile("a, b; r: a then {x(Y), y(Y)};");
+    compile("{c(X), d(X)}; r: {c(X), $p} then {e(X), $p};");
+}
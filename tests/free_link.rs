@@ -0,0 +1,13 @@
+mod common;
+
+#[test]
+fn free_link() {
+    // library callers get the link errors without running the analyses themselves
+    assert_eq!(
+        common::errors("a(X), b(Y, Y, Y);"),
+        [
+            "link `X` is not connected",
+            "link `Y` is used 3 times, but a link connects exactly two ports",
+        ]
+    );
+}
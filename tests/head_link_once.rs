@@ -0,0 +1,29 @@
+mod common;
+
+use common::{compile, errors, rule};
+
+#[test]
+fn head_link_once() {
+    assert_eq!(
+        errors("a(1); r: a(X) then b;"),
+        ["link `X` of the head of rule `r` is used only once"]
+    );
+    // a data atom the guard checks the type of is removed with the head
+    let il = compile("a(1); r: a(X) when int(X); then b;");
+    assert!(il.contains("remove_atom"));
+
+    // the body closes its own `X`, the one of the head is left dangling
+    assert_eq!(
+        errors("a(X), b(X); r: a(X) then c(X), d(X);"),
+        ["link `X` is used 3 times, but a link connects exactly two ports"]
+    );
+
+    // a link between two ports of the same atom is made once
+    let il = compile("a; r: a then c(X, X);");
+    assert_eq!(
+        rule(&il, "r").matches("new_link").count(),
+        1,
+        "{}",
+        rule(&il, "r")
+    );
+}
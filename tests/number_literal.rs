@@ -0,0 +1,20 @@
+mod common;
+
+use common::{compile, errors, rule};
+
+#[test]
+fn number_literal() {
+    assert_eq!(
+        errors("a(1); r: a(X) when X > 99999999999999999999; then b;"),
+        ["integer `99999999999999999999` does not fit in 64 bits"]
+    );
+    assert_eq!(
+        errors("a(99999999999999999999);"),
+        ["integer `99999999999999999999` does not fit in 64 bits"]
+    );
+
+    // hexadecimal integers are read in atoms and guards, and named in decimal
+    let il = compile("a(0x10); r: a(X) when X == 0x10; then b(X);");
+    assert!(il.contains("new_atom\t2, 0, 16, 1\n"), "{}", il);
+    assert!(rule(&il, "r").contains("load_int\t3, 16\n"), "{}", il);
+}
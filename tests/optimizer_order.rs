@@ -0,0 +1,48 @@
+use liblmntalc::{
+    codegen::ILGenerator,
+    optimizer::{Optimizer, OptimizerManager},
+};
+
+#[derive(Debug, Default)]
+struct First;
+
+#[derive(Debug, Default)]
+struct Second;
+
+macro_rules! optimizer {
+    ($name:ident) => {
+        impl Optimizer for $name {
+            fn optimize(&self, _: &mut ILGenerator) {}
+
+            fn pass(&self) -> u8 {
+                1
+            }
+
+            fn level(&self) -> u8 {
+                0
+            }
+
+            fn order(&self) -> i32 {
+                0
+            }
+
+            fn set_order(&mut self, _: i32) {}
+        }
+    };
+}
+
+optimizer!(First);
+optimizer!(Second);
+
+#[test]
+fn optimizer_order() {
+    // optimizers of the same order are different optimizers all the same
+    let mut manager = OptimizerManager::new(0);
+    manager.add_optimizer(Box::new(First));
+    manager.add_optimizer(Box::new(Second));
+    manager.add_optimizer(Box::new(First));
+
+    let mut names = manager.disable("Third").unwrap_err();
+    names.sort();
+    assert_eq!(names, ["First", "Second"]);
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::{fmt::Write, time::Instant};
+
+use common::compile;
+
+/// Generated initial processes far larger than anything written by hand: compiling four times
+/// as many atoms must take about four times as long, where a pipeline quadratic in the number
+/// of atoms or links takes sixteen.
+#[test]
+fn large_initial_process() {
+    const NODES: usize = 8_000;
+    // the fastest of a few runs, the others may be slowed down by the tests running beside
+    let time = |nodes: usize| {
+        let source = program(nodes);
+        (0..2)
+            .map(|_| {
+                let start = Instant::now();
+                let il = compile(&source);
+                (start.elapsed(), il)
+            })
+            .min_by_key(|(elapsed, _)| *elapsed)
+            .unwrap()
+    };
+    let (small, _) = time(NODES / 4);
+    let (large, il) = time(NODES);
+    let ratio = large.as_secs_f64() / small.as_secs_f64();
+    assert!(
+        ratio < 10.0,
+        "{} nodes take {:?}, {} take {:?}, {:.1} times as long",
+        NODES / 4,
+        small,
+        NODES,
+        large,
+        ratio
+    );
+
+    let count = |op: &str| il.lines().filter(|l| l.starts_with(op)).count();
+    let mems = NODES / 100;
+    // each node holds `val`, which holds a number, and `done` comes last
+    assert_eq!(count("new_atom\t"), 3 * NODES + 2 * mems + 1);
+    assert_eq!(count("new_mem"), mems);
+    // the ring, the nested atoms and the link in each membrane
+    assert_eq!(count("new_link\t"), 3 * NODES + mems);
+    // the last node closes the ring
+    assert!(il.contains(&format!("new_link\t1, 0, {}, 1, 0\n", last_node(&il))));
+}
+
+/// A ring of `nodes` atoms, each holding a nested atom, with a membrane of two more atoms
+/// after every hundredth one.
+fn program(nodes: usize) -> String {
+    let mut source = String::new();
+    for i in 0..nodes {
+        _ = write!(source, "node(L{}, L{}, val({}))", i, (i + 1) % nodes, i);
+        if i % 100 == 0 {
+            _ = write!(source, ", m{{ a(M{}), b(M{}) }}", i, i);
+        }
+        source.push_str(if i % 8 == 7 { ";\n" } else { ", " });
+    }
+    source.push_str("done");
+    source
+}
+
+/// The register holding the last `node` atom created.
+fn last_node(il: &str) -> usize {
+    let line = il
+        .lines()
+        .rev()
+        .find(|l| l.starts_with("new_atom\t") && l.ends_with(", node, 3"))
+        .unwrap();
+    line["new_atom\t".len()..]
+        .split(',')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use common::{compile, compile_optimized, rule};
+
+#[test]
+fn pattern_order() {
+    const SOURCE: &str = "val(1, A), link(A, B), val(2, B), go;
+walk: go, val(I, A), link(A, B), val(J, B) when int(I) && int(J); then went(I, J);";
+    let il = compile(SOURCE);
+    // the removed atoms are not reused
+    let optimized = compile_optimized(SOURCE, 1, &["RuleOptimizer"]);
+
+    assert_eq!(
+        rule(&il, "walk"),
+        "Rule walk
+Registers 6
+Block 0
+find_atom\t1, 0, go, 0
+find_atom\t2, 0, val, 2
+find_atom\t3, 0, link, 2
+deref   \t4, 2, 1, 0
+eqatom  \t4, 3
+find_atom\t4, 0, val, 2
+neqatom \t2, 4
+deref   \t5, 3, 1, 1
+eqatom  \t5, 4
+branch  \t1
+fail
+Block 1
+deref_atom\t5, 2, 0
+isint   \t5
+deref_atom\t5, 4, 0
+isint   \t5
+commit
+remove_atom\t4, 0
+remove_atom\t3, 0
+remove_atom\t2, 0
+remove_atom\t1, 0
+new_atom\t1, 0, went, 2
+relink\t1, 0, 2, 0, 0
+relink\t1, 1, 4, 0, 0
+proceed
+"
+    );
+    // `go` and `link` are the rarest, `link` leads to both `val`s,
+    // and the value of each `val` is checked as soon as it is reached
+    assert_eq!(
+        rule(&optimized, "walk"),
+        "Rule walk
+Registers 6
+Block 0
+find_atom\t1, 0, go, 0
+find_atom\t2, 0, link, 2
+deref   \t3, 2, 0, 1
+func    \t3, val, 2
+deref_atom\t4, 3, 0
+isint   \t4
+deref   \t4, 2, 1, 1
+func    \t4, val, 2
+neqatom \t3, 4
+deref_atom\t5, 4, 0
+isint   \t5
+branch  \t1
+fail
+Block 1
+commit
+remove_atom\t4, 0
+remove_atom\t2, 0
+remove_atom\t3, 0
+remove_atom\t1, 0
+new_atom\t1, 0, went, 2
+relink\t1, 0, 3, 0, 0
+relink\t1, 1, 4, 0, 0
+proceed
+"
+    );
+}
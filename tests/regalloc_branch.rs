@@ -0,0 +1,97 @@
+use liblmntalc::{
+    codegen::{
+        il::{BlockId, Instr, Port, Reg, IL},
+        RuleIL,
+    },
+    functor::FunctorTable,
+    parser::rule_parser::GuardOperator,
+};
+
+/// A value the head loads stays live through a case whose guard fails, since the next case
+/// reads it: the registers of the failing guard may not reuse its register.
+#[test]
+fn value_live_across_failed_case() {
+    let mut functors = FunctorTable::default();
+    let a = functors.intern("a", 1);
+    let blocks = vec![
+        vec![
+            IL::LoadInt {
+                dst: Reg(1),
+                value: 5,
+            },
+            IL::Branch { target: BlockId(1) },
+            IL::Branch { target: BlockId(2) },
+            IL::Fail,
+        ],
+        // a guard that computes in temporaries and may fail, never reading the value
+        vec![
+            IL::LoadInt {
+                dst: Reg(2),
+                value: 1,
+            },
+            IL::LoadInt {
+                dst: Reg(3),
+                value: 2,
+            },
+            IL::IntOp {
+                op: GuardOperator::Add,
+                dst: Reg(4),
+                lhs: Reg(2),
+                rhs: Reg(3),
+            },
+            IL::IntCmp {
+                op: GuardOperator::Gt,
+                lhs: Reg(4),
+                rhs: Reg(3),
+            },
+            IL::Commit,
+            IL::Proceed,
+        ],
+        vec![
+            IL::Commit,
+            IL::NewInt {
+                dst: Reg(5),
+                mem: Reg(0),
+                src: Reg(1),
+            },
+            IL::NewAtom {
+                dst: Reg(6),
+                mem: Reg(0),
+                functor: a,
+            },
+            IL::NewLink {
+                atom1: Reg(5),
+                port1: Port(0),
+                atom2: Reg(6),
+                port2: Port(0),
+                mem: Reg(0),
+            },
+            IL::Proceed,
+        ],
+    ];
+    let mut rule = RuleIL {
+        name: "test".to_string(),
+        blocks: blocks
+            .into_iter()
+            .map(|block| block.into_iter().map(Instr::from).collect())
+            .collect(),
+        ..Default::default()
+    };
+
+    rule.allocate_registers();
+
+    assert!(rule.verify(&functors).is_empty());
+    let value = rule.blocks[0][0].il.writes().unwrap();
+    for instr in &rule.blocks[1] {
+        assert_ne!(
+            instr.il.writes(),
+            Some(value),
+            "`{}` overwrites the value block 2 reads",
+            instr.il
+        );
+    }
+    let IL::NewInt { src, .. } = rule.blocks[2][1].il else {
+        unreachable!()
+    };
+    assert_eq!(src, value);
+}
@@ -0,0 +1,18 @@
+mod common;
+
+use common::{compile, rule};
+
+#[test]
+fn int_module() {
+    let il = compile("use(int); use(list); int.range(1, 3, R), list.reverse(R, S), out(S);");
+
+    // `int` uses `list`, which is loaded only once
+    assert_eq!(il.matches("Rule append_cons\n").count(), 1);
+    // an empty range becomes the empty list, taking over the link of the result
+    assert!(rule(&il, "range_nil").contains(
+        "remove_atom\t1, 0\n\
+         new_atom\t2, 0, list.nil, 1\n\
+         relink\t2, 0, 1, 2, 0\n\
+         proceed\n"
+    ));
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::{compile, rule};
+
+#[test]
+fn list_module() {
+    let il = compile("use(list); list.append(list.cons(1, list.nil), list.nil, R), out(R);");
+
+    for name in [
+        "append_cons",
+        "append_nil",
+        "reverse",
+        "reverse_onto",
+        "length",
+        "count_cons",
+        "count_nil",
+    ] {
+        assert!(il.contains(name), "no `{}` in\n{}", name, il);
+    }
+    // reversing starts from an empty accumulator linked to the second port
+    assert!(rule(&il, "reverse").contains(
+        "new_atom\t2, 0, list.reverse_onto, 3\n\
+         new_atom\t3, 0, list.nil, 1\n\
+         new_link\t2, 1, 3, 0, 0\n\
+         relink\t2, 0, 1, 0, 0\n\
+         relink\t2, 2, 1, 1, 0\n\
+         proceed\n"
+    ));
+}
@@ -0,0 +1,24 @@
+mod common;
+
+use common::{compile, rule};
+
+#[test]
+fn set_module() {
+    let il = compile("use(set); set{set.elem(1), set.elem(2), set.elem(1), set.remove(2)};");
+
+    // both sets are merged into the new one
+    assert_eq!(rule(&il, "union").matches("move_cells").count(), 2);
+    assert!(rule(&il, "remove").contains("find_atom\t3, 1, set.remove, 1\n"));
+    // the value and the removal are dropped, what is left goes into a new set
+    assert!(rule(&il, "remove").contains(
+        "remove_ground\t4, 1\n\
+         remove_ground\t5, 1\n\
+         remove_atom\t3, 1\n\
+         remove_atom\t2, 1\n\
+         remove_mem\t1, 0\n\
+         new_mem \t2, 0\n\
+         set_mem_name\t2, set\n\
+         move_cells\t2, 1\n\
+         proceed\n"
+    ));
+}
@@ -0,0 +1,37 @@
+mod common;
+
+use common::{compile, rule};
+
+#[test]
+fn temp_var_body() {
+    let il =
+        compile("a(1), b(2); r: a(X), b(Y) when int(X) && int(Y); with Z := X + Y; then c(Z);");
+
+    // the sum becomes a data atom of `c`, the numbers it is computed from are removed
+    assert_eq!(
+        rule(&il, "r"),
+        "Rule r
+Registers 6
+Block 0
+find_atom\t1, 0, a, 1
+find_atom\t2, 0, b, 1
+branch  \t1
+fail
+Block 1
+deref_atom\t3, 1, 0
+isint   \t3
+deref_atom\t4, 2, 0
+isint   \t4
+iadd    \t5, 3, 4
+commit
+remove_atom\t3, 0
+remove_atom\t4, 0
+remove_atom\t2, 0
+remove_atom\t1, 0
+new_atom\t1, 0, c, 1
+new_int \t2, 0, 5
+new_link\t1, 0, 2, 0, 0
+proceed
+"
+    );
+}
@@ -0,0 +1,10 @@
+mod common;
+
+#[test]
+fn temp_var_order() {
+    // `Y` is compared before the rule would otherwise reach its `with` clause
+    let il = common::compile("a(1); r: a(X) when int(X); with Y := X + 1; when Y > 2; then b(Y);");
+    let rule = common::rule(&il, "r");
+    let (add, cmp) = (rule.find("iadd").unwrap(), rule.find("igt").unwrap());
+    assert!(add < cmp, "{}", rule);
+}
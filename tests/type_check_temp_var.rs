@@ -0,0 +1,11 @@
+mod common;
+
+#[test]
+fn type_check_temp_var() {
+    let errors =
+        common::errors("a(1); r: a(X) when int(X); with Z := X + 1; when int(Z); then b(Z);");
+    assert_eq!(
+        errors,
+        ["`int` checks a link or process context of the head, found temporary variable `Z`",]
+    );
+}
@@ -0,0 +1,282 @@
+use liblmntalc::{
+    codegen::{
+        il::{BlockId, Port, Reg, IL},
+        RuleIL,
+    },
+    functor::FunctorTable,
+};
+
+/// The messages of the errors the verifier reports on a rule made of `blocks`,
+/// the functors it uses being those of `functors`.
+fn malformed(functors: &FunctorTable, blocks: Vec<Vec<IL>>) -> Vec<String> {
+    let rule = RuleIL {
+        name: "test".to_string(),
+        blocks: blocks
+            .into_iter()
+            .map(|block| block.into_iter().map(Into::into).collect())
+            .collect(),
+        ..Default::default()
+    };
+    rule.verify(functors)
+        .into_iter()
+        .map(|d| d.message)
+        .collect()
+}
+
+/// A register read before anything is written to it is reported.
+#[test]
+fn read_before_write() {
+    let mut functors = FunctorTable::default();
+    let a = functors.intern("a", 2);
+    let rule = |find: bool| {
+        let mut block = vec![];
+        if find {
+            block.push(IL::FindAtom {
+                dst: Reg(1),
+                mem: Reg(0),
+                functor: a,
+            });
+        }
+        block.push(IL::RemoveAtom {
+            atom: Reg(1),
+            mem: Reg(0),
+        });
+        block.push(IL::Proceed);
+        vec![block]
+    };
+
+    assert_eq!(malformed(&functors, rule(true)), Vec::<String>::new());
+    assert_eq!(
+        malformed(&functors, rule(false)),
+        ["malformed IL: register 1 is read before it is written"]
+    );
+}
+
+/// An atom used where a membrane is expected is reported.
+#[test]
+fn wrong_register_kind() {
+    let mut functors = FunctorTable::default();
+    let a = functors.intern("a", 0);
+    let rule = |mem: Reg| {
+        vec![vec![
+            IL::NewAtom {
+                dst: Reg(1),
+                mem: Reg(0),
+                functor: a,
+            },
+            IL::NewAtom {
+                dst: Reg(2),
+                mem,
+                functor: a,
+            },
+            IL::Proceed,
+        ]]
+    };
+
+    assert_eq!(malformed(&functors, rule(Reg(0))), Vec::<String>::new());
+    assert_eq!(
+        malformed(&functors, rule(Reg(1))),
+        ["malformed IL: register 1 holds an atom where a membrane is expected"]
+    );
+}
+
+/// A port past the arity of the atom it is taken from is reported, whether it is
+/// dereferenced or linked.
+#[test]
+fn port_beyond_arity() {
+    let mut functors = FunctorTable::default();
+    let a = functors.intern("a", 2);
+    let deref = |port: usize| {
+        vec![vec![
+            IL::FindAtom {
+                dst: Reg(1),
+                mem: Reg(0),
+                functor: a,
+            },
+            IL::DerefAtom {
+                dst: Reg(2),
+                src: Reg(1),
+                port: Port(port),
+            },
+            IL::Proceed,
+        ]]
+    };
+    let new_link = |port: usize| {
+        vec![vec![
+            IL::FindAtom {
+                dst: Reg(1),
+                mem: Reg(0),
+                functor: a,
+            },
+            IL::FindAtom {
+                dst: Reg(2),
+                mem: Reg(0),
+                functor: a,
+            },
+            IL::NewLink {
+                atom1: Reg(1),
+                port1: Port(port),
+                atom2: Reg(2),
+                port2: Port(0),
+                mem: Reg(0),
+            },
+            IL::Proceed,
+        ]]
+    };
+
+    let beyond = ["malformed IL: port 2 does not exist on the atom of arity 2 in register 1"];
+    assert_eq!(malformed(&functors, deref(1)), Vec::<String>::new());
+    assert_eq!(malformed(&functors, deref(2)), beyond);
+    assert_eq!(malformed(&functors, new_link(1)), Vec::<String>::new());
+    assert_eq!(malformed(&functors, new_link(2)), beyond);
+}
+
+/// An atom removed from its membrane twice is reported.
+#[test]
+fn double_remove() {
+    let mut functors = FunctorTable::default();
+    let a = functors.intern("a", 0);
+    let rule = |removals: usize| {
+        let mut block = vec![IL::FindAtom {
+            dst: Reg(1),
+            mem: Reg(0),
+            functor: a,
+        }];
+        for _ in 0..removals {
+            block.push(IL::RemoveAtom {
+                atom: Reg(1),
+                mem: Reg(0),
+            });
+        }
+        block.push(IL::Proceed);
+        vec![block]
+    };
+
+    assert_eq!(malformed(&functors, rule(1)), Vec::<String>::new());
+    assert_eq!(
+        malformed(&functors, rule(2)),
+        ["malformed IL: register 1 is removed twice"]
+    );
+}
+
+/// A branch to a block the rule does not have is reported.
+#[test]
+fn missing_block() {
+    let functors = FunctorTable::default();
+    let rule = |target: usize| {
+        vec![
+            vec![
+                IL::Branch {
+                    target: BlockId(target),
+                },
+                IL::Fail,
+            ],
+            vec![IL::Proceed],
+        ]
+    };
+
+    assert_eq!(malformed(&functors, rule(1)), Vec::<String>::new());
+    assert_eq!(
+        malformed(&functors, rule(2)),
+        ["malformed IL: block 2 does not exist"]
+    );
+}
+
+/// An atom linked to after it is removed is reported, taking a link over from it is not.
+#[test]
+fn use_after_removal() {
+    let mut functors = FunctorTable::default();
+    let a = functors.intern("a", 1);
+    let rule = |link: fn(Reg, Port, Reg, Port, Reg) -> IL| {
+        vec![vec![
+            IL::FindAtom {
+                dst: Reg(1),
+                mem: Reg(0),
+                functor: a,
+            },
+            IL::RemoveAtom {
+                atom: Reg(1),
+                mem: Reg(0),
+            },
+            IL::NewAtom {
+                dst: Reg(2),
+                mem: Reg(0),
+                functor: a,
+            },
+            link(Reg(2), Port(0), Reg(1), Port(0), Reg(0)),
+            IL::Proceed,
+        ]]
+    };
+
+    let relink = |atom1, port1, atom2, port2, mem| IL::ReLink {
+        atom1,
+        port1,
+        atom2,
+        port2,
+        mem,
+    };
+    let new_link = |atom1, port1, atom2, port2, mem| IL::NewLink {
+        atom1,
+        port1,
+        atom2,
+        port2,
+        mem,
+    };
+    assert_eq!(malformed(&functors, rule(relink)), Vec::<String>::new());
+    assert_eq!(
+        malformed(&functors, rule(new_link)),
+        ["malformed IL: register 1 holds an atom that was removed"]
+    );
+}
+
+/// A port of an atom the rule creates left without a link, or linked twice, is reported.
+#[test]
+fn created_port_linked_once() {
+    let mut functors = FunctorTable::default();
+    let a = functors.intern("a", 1);
+    let rule = |links: usize| {
+        let mut block = vec![
+            IL::LoadInt {
+                dst: Reg(3),
+                value: 1,
+            },
+            IL::NewAtom {
+                dst: Reg(1),
+                mem: Reg(0),
+                functor: a,
+            },
+            IL::NewInt {
+                dst: Reg(2),
+                mem: Reg(0),
+                src: Reg(3),
+            },
+        ];
+        for _ in 0..links {
+            block.push(IL::NewLink {
+                atom1: Reg(1),
+                port1: Port(0),
+                atom2: Reg(2),
+                port2: Port(0),
+                mem: Reg(0),
+            });
+        }
+        block.push(IL::Proceed);
+        vec![block]
+    };
+
+    assert_eq!(malformed(&functors, rule(1)), Vec::<String>::new());
+    assert_eq!(
+        malformed(&functors, rule(0)),
+        [
+            "malformed IL: port 0 of the atom `a` created in register 1 is linked 0 times",
+            "malformed IL: port 0 of the number created in register 2 is linked 0 times",
+        ]
+    );
+    assert_eq!(
+        malformed(&functors, rule(2)),
+        [
+            "malformed IL: port 0 of the atom `a` created in register 1 is linked 2 times",
+            "malformed IL: port 0 of the number created in register 2 is linked 2 times",
+        ]
+    );
+}